@@ -0,0 +1,132 @@
+use nalgebra::{DMatrix, DVector};
+
+use crate::homogenize::{homogenize, material, Homogeneous};
+use crate::{Mesh, SolutionResults, Variables, XSData};
+
+// Multigroup k of a homogeneous material with a leakage D B^2 in every group,
+//     k = nu sigf^T (diag(sigt + D B^2) - S^T)^-1 chi
+// which is k infinity for B^2 = 0. None when nothing is removed from some group.
+fn k_buckling(material: &Homogeneous, buckling: f64) -> Option<f64> {
+    let groups = material.sigt.len();
+    let removal = DMatrix::<f64>::from_fn(groups, groups, |to, from| {
+        let diagonal = match to == from {
+            true => material.sigt[to] + material.diffusion[to] * buckling,
+            false => 0.0,
+        };
+        diagonal - material.scatter[from][to]
+    });
+    let flux = removal
+        .lu()
+        .solve(&DVector::from_vec(material.chi.clone()))?;
+    Some(
+        flux.iter()
+            .zip(material.nu_sigf.iter())
+            .map(|(x, y)| x * y)
+            .sum(),
+    )
+}
+
+// Fundamental mode buckling of a bare slab of width w with Marshak boundaries,
+//     B tan(B w / 2) = 1 / (2 D)
+// solved by bisection below the pi / w of a zero flux boundary
+pub fn slab_buckling(width: f64, diffusion: f64) -> f64 {
+    let (mut low, mut high) = (0.0, std::f64::consts::PI / width);
+    for _ in 0..100 {
+        let b = 0.5 * (low + high);
+        match b * (0.5 * b * width).tan() < 0.5 / diffusion {
+            true => low = b,
+            false => high = b,
+        }
+    }
+    (0.5 * (low + high)).powi(2)
+}
+
+// Analytic references for Analk = 1: k infinity of every material with its full scattering
+// matrix, k infinity of the flux weighted homogenized problem and, for one and two group data,
+// the diffusion k of a bare slab of the homogenized material as wide as the problem
+pub fn analytic_k(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) {
+    let groups = variables.energygroups as usize;
+    for matid in 0..variables.mattypes as usize {
+        let local = material(xsdata, variables, matid);
+        if local.nu_sigf.iter().all(|x| *x == 0.0) {
+            continue;
+        }
+        match k_buckling(&local, 0.0) {
+            Some(k) => println!("analytic: material {} k_inf {:.6}", matid, k),
+            None => println!("analytic: material {} k_inf undefined", matid),
+        }
+    }
+
+    let homogeneous = homogenize(&results.flux, xsdata, meshid, variables, 0..meshid.len());
+    let k_inf = k_buckling(&homogeneous, 0.0).unwrap_or(f64::NAN);
+    let numerical = results.k_eff() as f64;
+    println!(
+        "analytic: homogenized k_inf {:.6}, numerical k {:.6}",
+        k_inf, numerical
+    );
+
+    if groups <= 2 {
+        let width: f64 = meshid.iter().map(|mesh| mesh.delta_x as f64).sum();
+        // One group diffusion coefficient weighted by the homogenized group fluxes, like the
+        // other constants
+        let group_flux: Vec<f64> = (0..groups)
+            .map(|g| match results.flux.get(g) {
+                Some(flux) => flux
+                    .iter()
+                    .zip(meshid.iter())
+                    .map(|(x, mesh)| (x * mesh.delta_x) as f64)
+                    .sum(),
+                None => 1.0,
+            })
+            .collect();
+        let diffusion = homogeneous
+            .diffusion
+            .iter()
+            .zip(group_flux.iter())
+            .map(|(d, phi)| d * phi)
+            .sum::<f64>()
+            / group_flux.iter().sum::<f64>();
+        let buckling = slab_buckling(width, diffusion);
+        println!(
+            "analytic: bare slab of {:.4} cm, buckling {:.6e} 1/cm^2, k {:.6}",
+            width,
+            buckling,
+            k_buckling(&homogeneous, buckling).unwrap_or(f64::NAN)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analytic::{k_buckling, slab_buckling};
+    use crate::discrete::tests::two_group_medium;
+    use crate::homogenize::homogenize;
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_two_group_k() {
+        // Fast and thermal groups with downscatter only, k infinity is 7 / 6
+        let (xsdata, meshid, variables) = two_group_medium();
+        let homogeneous = homogenize(&[], &xsdata, &meshid, &variables, 0..meshid.len());
+        assert!(k_buckling(&homogeneous, 0.0)
+            .unwrap()
+            .approx_eq(7.0 / 6.0, (1e-6, 2)));
+
+        // With leakage the removal of each group grows by D B^2
+        let (d1, d2, b2) = (1.0 / 0.6, 1.0 / 3.0, 1e-3);
+        let (r1, r2) = (0.03 + d1 * b2, 0.1 + d2 * b2);
+        let k = 0.005 / r1 + 0.02 * 0.15 / (r1 * r2);
+        assert!(k_buckling(&homogeneous, b2)
+            .unwrap()
+            .approx_eq(k, (1e-6, 2)));
+
+        // A thick slab approaches the zero flux buckling with the extrapolation distance 2 D
+        let b2 = slab_buckling(1000.0, 1.0);
+        assert!(b2.approx_eq((std::f64::consts::PI / 1004.0).powi(2), (1e-9, 2)));
+    }
+}
@@ -0,0 +1,361 @@
+use csv::Writer;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::cmfd::coarse_cells;
+use crate::discrete::boundary_coupling;
+use crate::{CoarseMesh, Mesh, Method, SolutionResults, SolverSettings, Variables, XSData};
+
+// Reaction rates of every region and group per unit height, a region being a run of meshes of
+// one material. The fission source is born in the group, divided by k for an eigenvalue, and
+// the leakage is the net finite difference current out of the region, through the problem
+// boundaries included.
+pub struct Balance {
+    pub regions: Vec<(usize, usize)>, // [start, end) meshes of every region
+    pub material: Vec<usize>,         // material of every region
+    pub production: Vec<Vec<f64>>,    // nu fission rate of the neutrons of the group
+    pub source: Vec<Vec<f64>>,        // fission and external neutrons born in the group
+    pub absorption: Vec<Vec<f64>>,    // removal that is not scattering, sigt - sigs
+    pub in_scatter: Vec<Vec<f64>>,
+    pub out_scatter: Vec<Vec<f64>>,
+    pub leakage: Vec<Vec<f64>>,
+    pub boundary: Vec<[f64; 2]>, // net current out of the left and right boundaries per group
+    pub k: f64,                  // k the fission source is divided by
+}
+
+impl Balance {
+    // Gains less losses of one region and group
+    pub fn closure(&self, region: usize, g: usize) -> f64 {
+        self.source[region][g] + self.in_scatter[region][g]
+            - self.absorption[region][g]
+            - self.out_scatter[region][g]
+            - self.leakage[region][g]
+    }
+
+    // Relative difference of all neutrons born and all neutrons absorbed or leaked
+    pub fn global_closure(&self) -> f64 {
+        let born: f64 = self.source.iter().flatten().sum();
+        let lost: f64 = self.absorption.iter().flatten().sum::<f64>()
+            + self.boundary.iter().map(|x| x[0] + x[1]).sum::<f64>();
+        (born - lost) / lost
+    }
+}
+
+pub fn neutron_balance(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    method: &Method,
+    settings: &SolverSettings,
+) -> Result<Balance, Box<dyn Error>> {
+    // The fixed source solution keeps k for its source multiplication instead
+    let (k, external, fission) = match method {
+        Method::Diffusion | Method::Kinetics => (results.k[0] as f64, false, true),
+        Method::FixedSource => (1.0, true, settings.multiply),
+        _ => return Err("the balance needs the finite difference diffusion solution".into()),
+    };
+    let (groups, mattypes, n) = (
+        variables.energygroups as usize,
+        variables.mattypes as usize,
+        meshid.len(),
+    );
+    let at = |field: &[f32], x: usize, g: usize| -> f64 {
+        field[meshid[x].matid as usize + mattypes * g] as f64
+    };
+    let scatter = |x: usize, from: usize, to: usize| -> f64 {
+        xsdata.scat_matrix[groups.pow(2) * meshid[x].matid as usize + groups * from + to] as f64
+    };
+    let flux = |g: usize, x: usize| results.flux[g][x] as f64;
+    let d = |x: usize, g: usize| at(&xsdata.inv_sigtr, x, g) / 3.0 / meshid[x].delta_x as f64;
+
+    let regions = coarse_cells(meshid, &CoarseMesh::Pin, variables.numass);
+    let mut region = vec![0; n];
+    for (r, &(start, end)) in regions.iter().enumerate() {
+        region[start..end].fill(r);
+    }
+
+    let mut table = vec![vec![0.0; groups]; regions.len()];
+    let (mut production, mut source, mut absorption) =
+        (table.clone(), table.clone(), table.clone());
+    let (mut in_scatter, mut out_scatter) = (table.clone(), table.clone());
+    for (x, mesh) in meshid.iter().enumerate() {
+        let (r, dx) = (region[x], mesh.delta_x as f64);
+        let fission_rate: f64 = (0..groups)
+            .map(|g| at(&xsdata.nut, x, g) * at(&xsdata.sigf, x, g) * flux(g, x))
+            .sum();
+        for g in 0..groups {
+            production[r][g] += at(&xsdata.nut, x, g) * at(&xsdata.sigf, x, g) * flux(g, x) * dx;
+            if fission {
+                source[r][g] += at(&xsdata.chit, x, g) * fission_rate * dx / k;
+            }
+            if external {
+                source[r][g] += at(&xsdata.source, x, g) * dx;
+            }
+            let scattered: f64 = (0..groups).map(|to| scatter(x, g, to)).sum();
+            absorption[r][g] += (at(&xsdata.sigt, x, g) - scattered) * flux(g, x) * dx;
+            for h in (0..groups).filter(|h| *h != g) {
+                out_scatter[r][g] += scatter(x, g, h) * flux(g, x) * dx;
+                in_scatter[r][g] += scatter(x, h, g) * flux(h, x) * dx;
+            }
+        }
+    }
+
+    // Currents across the faces between regions, with the harmonic coupling of the operator,
+    // and through the boundaries
+    let mut boundary = vec![[0.0; 2]; groups];
+    for g in 0..groups {
+        for x in 0..n - 1 {
+            let (left, right) = (region[x], region[x + 1]);
+            if left != right {
+                let coupling = 2.0 * d(x, g) * d(x + 1, g) / (d(x, g) + d(x + 1, g));
+                let current = coupling * (flux(g, x) - flux(g, x + 1));
+                table[left][g] += current;
+                table[right][g] -= current;
+            }
+        }
+        boundary[g] = [
+            boundary_coupling(variables.boundl, d(0, g) as f32) as f64 * flux(g, 0),
+            boundary_coupling(variables.boundr, d(n - 1, g) as f32) as f64 * flux(g, n - 1),
+        ];
+        table[0][g] += boundary[g][0];
+        table[regions.len() - 1][g] += boundary[g][1];
+    }
+
+    Ok(Balance {
+        material: regions
+            .iter()
+            .map(|&(start, _)| meshid[start].matid as usize)
+            .collect(),
+        regions,
+        production,
+        source,
+        absorption,
+        in_scatter,
+        out_scatter,
+        leakage: table,
+        boundary,
+        k,
+    })
+}
+
+// Table of every region and group with its material and edges, the closure, the share of the
+// absorption of the group in each region (the thermal utilization summed over the fuel), the
+// boundary leakage and the global balance, to balance.txt and the summary to the terminal
+pub fn write_balance(balance: &Balance, meshid: &[Mesh]) -> Result<(), Box<dyn Error>> {
+    let groups = balance.boundary.len();
+    let total = |field: &[Vec<f64>], g: usize| -> f64 { field.iter().map(|x| x[g]).sum() };
+    let edges = |r: usize| -> (f32, f32) {
+        let (start, end) = balance.regions[r];
+        (meshid[start].mesh_left, meshid[end - 1].mesh_right)
+    };
+    let mut file = BufWriter::new(File::create("./balance.txt")?);
+    writeln!(
+        file,
+        "Neutron balance per unit height, fission source / {:.6}",
+        balance.k
+    )?;
+    writeln!(file)?;
+    writeln!(
+        file,
+        "{:>6} {:>8} {:>9} {:>9} {:>5} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12} {:>10}",
+        "region",
+        "material",
+        "left",
+        "right",
+        "group",
+        "production",
+        "source",
+        "absorption",
+        "in_scatter",
+        "out_scatter",
+        "leakage",
+        "closure",
+        "abs_share"
+    )?;
+    for r in 0..balance.regions.len() {
+        let (left, right) = edges(r);
+        for g in 0..groups {
+            writeln!(
+                file,
+                "{:>6} {:>8} {:>9.4} {:>9.4} {:>5} {:>12.5e} {:>12.5e} {:>12.5e} {:>12.5e} {:>12.5e} {:>12.5e} {:>12.4e} {:>10.4}",
+                r + 1,
+                balance.material[r],
+                left,
+                right,
+                g + 1,
+                balance.production[r][g],
+                balance.source[r][g],
+                balance.absorption[r][g],
+                balance.in_scatter[r][g],
+                balance.out_scatter[r][g],
+                balance.leakage[r][g],
+                balance.closure(r, g),
+                balance.absorption[r][g] / total(&balance.absorption, g)
+            )?;
+        }
+    }
+    writeln!(file)?;
+    for (g, [left, right]) in balance.boundary.iter().enumerate() {
+        writeln!(
+            file,
+            "group {}: leakage left {:.5e} right {:.5e}, absorption {:.5e}, production {:.5e}",
+            g + 1,
+            left,
+            right,
+            total(&balance.absorption, g),
+            total(&balance.production, g)
+        )?;
+    }
+    let production: f64 = balance.production.iter().flatten().sum();
+    let absorption: f64 = balance.absorption.iter().flatten().sum();
+    let leakage: f64 = balance.boundary.iter().map(|x| x[0] + x[1]).sum();
+    let summary = format!(
+        "balance: production / k {:.6e}, absorption {:.6e}, leakage {:.6e}, \
+         production / (absorption + leakage) {:.6}, closure {:.3e}",
+        production / balance.k,
+        absorption,
+        leakage,
+        production / (absorption + leakage),
+        balance.global_closure()
+    );
+    writeln!(file)?;
+    writeln!(file, "{}", summary)?;
+    file.flush()?;
+    println!("{}", summary);
+
+    let mut wtr = Writer::from_path("./balance.csv")?;
+    wtr.write_record([
+        "region",
+        "material",
+        "left",
+        "right",
+        "group",
+        "production",
+        "source",
+        "absorption",
+        "in_scatter",
+        "out_scatter",
+        "leakage",
+        "closure",
+    ])?;
+    for r in 0..balance.regions.len() {
+        let (left, right) = edges(r);
+        for g in 0..groups {
+            let mut record = vec![
+                (r + 1).to_string(),
+                balance.material[r].to_string(),
+                left.to_string(),
+                right.to_string(),
+                (g + 1).to_string(),
+            ];
+            record.extend(
+                [
+                    balance.production[r][g],
+                    balance.source[r][g],
+                    balance.absorption[r][g],
+                    balance.in_scatter[r][g],
+                    balance.out_scatter[r][g],
+                    balance.leakage[r][g],
+                    balance.closure(r, g),
+                ]
+                .iter()
+                .map(|x| x.to_string()),
+            );
+            wtr.write_record(&record)?;
+        }
+    }
+    // The boundaries go in as two more regions that only leak
+    for (side, index) in [("left", 0), ("right", 1)] {
+        for (g, leakage) in balance.boundary.iter().enumerate() {
+            let mut record = vec![side.to_string()];
+            record.extend(["", "", ""].iter().map(|x| x.to_string()));
+            record.push((g + 1).to_string());
+            record.extend(["0"; 5].iter().map(|x| x.to_string()));
+            record.extend([leakage[index].to_string(), String::new()]);
+            wtr.write_record(&record)?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analytic::slab_buckling;
+    use crate::balance::neutron_balance;
+    use crate::discrete::nalgebra_method;
+    use crate::discrete::tests::{direct_settings, infinite_medium, library, slab_meshes};
+    use crate::{Method, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_bare_slab_balance() {
+        // A bare slab of 20 cm with Marshak vacuum boundaries. The finite difference k
+        // approaches nu sigf / (siga + D B^2) of the analytic buckling, and the balance of
+        // the converged flux closes with the leakage split evenly over both sides.
+        let (xsdata, _, mut variables) = infinite_medium(0.2);
+        (variables.boundl, variables.boundr) = (0.0, 0.0);
+        let meshid = slab_meshes(200, 0.1, |_| 0);
+        let settings = direct_settings();
+        let results = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+        let buckling = slab_buckling(20.0, 1.0 / 3.0);
+        let k = 0.2 / (0.1 + buckling / 3.0);
+        assert!((results.k[0] as f64).approx_eq(k, (1e-4, 2)));
+
+        let balance = neutron_balance(
+            &results,
+            &xsdata,
+            &meshid,
+            &variables,
+            &Method::Diffusion,
+            &settings,
+        )
+        .unwrap();
+        assert!(balance.global_closure().abs() < 1e-4);
+        assert!(balance.closure(0, 0).abs() < 1e-4 * balance.absorption[0][0]);
+        let [left, right] = balance.boundary[0];
+        assert!(left > 0.0);
+        assert!(left.approx_eq(right, (1e-3 * left, 2)));
+        assert!(balance.leakage[0][0].approx_eq(left + right, (1e-9, 2)));
+
+        // The same slab with its middle 4 cm under a second, identical material has three
+        // regions, and the two outer ones of material 0 are kept apart
+        let copy = |x: &[f32]| vec![x[0]; 2];
+        let xsdata = XSData {
+            sigt: copy(&xsdata.sigt),
+            sigs: copy(&xsdata.sigs),
+            siga: copy(&xsdata.siga),
+            sigf: copy(&xsdata.sigf),
+            nut: copy(&xsdata.nut),
+            chit: copy(&xsdata.chit),
+            scat_matrix: copy(&xsdata.scat_matrix),
+            inv_sigtr: copy(&xsdata.inv_sigtr),
+            ..library(2)
+        };
+        variables.mattypes = 2;
+        let meshid = slab_meshes(200, 0.1, |x| (80..120).contains(&x) as u8);
+        let results = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+        let balance = neutron_balance(
+            &results,
+            &xsdata,
+            &meshid,
+            &variables,
+            &Method::Diffusion,
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(balance.regions, vec![(0, 80), (80, 120), (120, 200)]);
+        assert_eq!(balance.material, vec![0, 1, 0]);
+        for r in 0..3 {
+            assert!(balance.closure(r, 0).abs() < 1e-4 * balance.absorption[r][0]);
+        }
+        // The middle region is a net source for the outer ones, which leak the same amount
+        assert!(balance.leakage[1][0] > 0.0);
+        assert!(balance.leakage[0][0].approx_eq(balance.leakage[2][0], (1e-3 * left, 2)));
+        let [left, right] = balance.boundary[0];
+        let leakage: f64 = balance.leakage.iter().map(|x| x[0]).sum();
+        assert!(leakage.approx_eq(left + right, (1e-6 * left, 2)));
+    }
+}
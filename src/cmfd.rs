@@ -0,0 +1,345 @@
+use crate::discrete::{assembly_ranges, TriDiagonal};
+use crate::{CoarseMesh, Mesh, Variables, XSData};
+
+// Convergence of the coarse mesh eigenvalue problem
+const COARSE_TOLERANCE: f64 = 1e-8;
+const COARSE_ITERATIONS: usize = 1000;
+
+// Groups the fine meshes into coarse cells, returned as [start, end) ranges. Pin cells are the
+// runs of meshes with the same material, assembly cells split the mesh the same way as the
+// assembly averages do.
+pub fn coarse_cells(meshid: &[Mesh], coarse_mesh: &CoarseMesh, numass: u8) -> Vec<(usize, usize)> {
+    let n = meshid.len();
+    match coarse_mesh {
+        CoarseMesh::Pin => {
+            let mut cells = Vec::new();
+            let mut start = 0;
+            for index in 1..n {
+                if meshid[index].matid != meshid[start].matid {
+                    cells.push((start, index));
+                    start = index;
+                }
+            }
+            cells.push((start, n));
+            cells
+        }
+        CoarseMesh::Assembly => assembly_ranges(n, numass)
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect(),
+        CoarseMesh::None => Vec::new(),
+    }
+}
+
+// The coarse mesh problem homogenized from a fine mesh flux. The coupling of every coarse
+// interface carries a correction (D hat) so the coarse net currents equal the fine ones.
+struct CoarseProblem {
+    lower: Vec<Vec<f64>>,
+    diag: Vec<Vec<f64>>,
+    upper: Vec<Vec<f64>>,
+    // Indexed [from][to][cell], already multiplied by the cell width
+    scattering: Vec<Vec<Vec<f64>>>,
+    fission: Vec<Vec<Vec<f64>>>,
+    // Cell averaged flux of every group
+    flux: Vec<Vec<f64>>,
+}
+
+impl CoarseProblem {
+    fn new(
+        flux: &[Vec<f32>],
+        a_matrix: &[TriDiagonal],
+        cells: &[(usize, usize)],
+        xsdata: &XSData,
+        meshid: &[Mesh],
+        variables: &Variables,
+    ) -> Self {
+        let (energygroups, mattypes) =
+            (variables.energygroups as usize, variables.mattypes as usize);
+        let m = cells.len();
+        let width: Vec<f64> = cells
+            .iter()
+            .map(|&(start, end)| (start..end).map(|i| meshid[i].delta_x as f64).sum())
+            .collect();
+        // Flux volume integral of a fine mesh quantity over every coarse cell
+        let integrate = |g: usize, value: &dyn Fn(usize) -> f64| -> Vec<f64> {
+            cells
+                .iter()
+                .map(|&(start, end)| {
+                    (start..end)
+                        .map(|i| value(i) * flux[g][i] as f64 * meshid[i].delta_x as f64)
+                        .sum()
+                })
+                .collect()
+        };
+
+        let average: Vec<Vec<f64>> = (0..energygroups)
+            .map(|g| {
+                integrate(g, &|_| 1.0)
+                    .iter()
+                    .zip(width.iter())
+                    .map(|(x, h)| x / h)
+                    .collect()
+            })
+            .collect();
+
+        let mut scattering = vec![vec![vec![0.0; m]; energygroups]; energygroups];
+        let mut fission = vec![vec![vec![0.0; m]; energygroups]; energygroups];
+        for from in 0..energygroups {
+            for to in 0..energygroups {
+                let production = integrate(from, &|i| {
+                    let matid = meshid[i].matid as usize;
+                    (xsdata.nut[matid + mattypes * from]
+                        * xsdata.sigf[matid + mattypes * from]
+                        * xsdata.chit[matid + mattypes * to]) as f64
+                });
+                for cell in 0..m {
+                    fission[from][to][cell] = production[cell] / average[from][cell];
+                }
+                // Within group scattering is part of the removal in the fine operator
+                if from != to {
+                    let transfer = integrate(from, &|i| {
+                        xsdata.scat_matrix[energygroups.pow(2) * meshid[i].matid as usize
+                            + energygroups * from
+                            + to] as f64
+                    });
+                    for cell in 0..m {
+                        scattering[from][to][cell] = transfer[cell] / average[from][cell];
+                    }
+                }
+            }
+        }
+
+        let (mut lower, mut diag, mut upper) = (
+            vec![vec![0.0; m]; energygroups],
+            vec![vec![0.0; m]; energygroups],
+            vec![vec![0.0; m]; energygroups],
+        );
+        for g in 0..energygroups {
+            let a = &a_matrix[g];
+            let diffusion: Vec<f64> = integrate(g, &|i| {
+                xsdata.inv_sigtr[meshid[i].matid as usize + mattypes * g] as f64 / 3.0
+            })
+            .iter()
+            .zip(average[g].iter().zip(width.iter()))
+            .map(|(x, (phi, h))| x / (phi * h))
+            .collect();
+
+            // Removal and boundary leakage, these are the row sums of the fine operator
+            for (cell, &(start, end)) in cells.iter().enumerate() {
+                diag[g][cell] = (start..end)
+                    .map(|i| a.row_sum[i] as f64 * flux[g][i] as f64)
+                    .sum::<f64>()
+                    / average[g][cell];
+            }
+
+            for cell in 0..m - 1 {
+                let last = cells[cell].1 - 1;
+                let current =
+                    a.upper[last] as f64 * (flux[g][last + 1] as f64 - flux[g][last] as f64);
+                let (phi_l, phi_r) = (average[g][cell], average[g][cell + 1]);
+                let d_tilde = 2.0 * diffusion[cell] * diffusion[cell + 1]
+                    / (diffusion[cell] * width[cell + 1] + diffusion[cell + 1] * width[cell]);
+                let d_hat = (current + d_tilde * (phi_r - phi_l)) / (phi_r + phi_l);
+
+                diag[g][cell] += d_tilde + d_hat;
+                upper[g][cell] = -(d_tilde - d_hat);
+                diag[g][cell + 1] += d_tilde - d_hat;
+                lower[g][cell + 1] = -(d_tilde + d_hat);
+            }
+        }
+
+        CoarseProblem {
+            lower,
+            diag,
+            upper,
+            scattering,
+            fission,
+            flux: average,
+        }
+    }
+
+    fn thomas(&self, g: usize, b: &[f64]) -> Vec<f64> {
+        let (lower, diag, upper) = (&self.lower[g], &self.diag[g], &self.upper[g]);
+        let n = diag.len();
+        let mut c_prime = vec![0.0; n];
+        let mut x = vec![0.0; n];
+        c_prime[0] = upper[0] / diag[0];
+        x[0] = b[0] / diag[0];
+        for i in 1..n {
+            let denominator = diag[i] - lower[i] * c_prime[i - 1];
+            c_prime[i] = upper[i] / denominator;
+            x[i] = (b[i] - lower[i] * x[i - 1]) / denominator;
+        }
+        for i in (0..n - 1).rev() {
+            x[i] -= c_prime[i] * x[i + 1];
+        }
+        x
+    }
+
+    fn production(&self, flux: &[Vec<f64>]) -> f64 {
+        let energygroups = flux.len();
+        (0..energygroups)
+            .flat_map(|from| (0..energygroups).map(move |to| (from, to)))
+            .map(|(from, to)| {
+                self.fission[from][to]
+                    .iter()
+                    .zip(flux[from].iter())
+                    .map(|(f, phi)| f * phi)
+                    .sum::<f64>()
+            })
+            .sum()
+    }
+
+    // Power iteration on the coarse problem, starting from the homogenized flux. The returned
+    // flux keeps the fission production of the starting flux.
+    fn solve(&self, k: f32) -> (Vec<Vec<f64>>, f64, usize) {
+        let energygroups = self.flux.len();
+        let m = self.flux[0].len();
+        let mut flux = self.flux.clone();
+        let mut k = k as f64;
+        let initial_production = self.production(&flux);
+        let mut production = initial_production;
+
+        let mut iterations = 0;
+        while iterations < COARSE_ITERATIONS {
+            let previous = flux.clone();
+            for to in 0..energygroups {
+                let source: Vec<f64> = (0..m)
+                    .map(|cell| {
+                        (0..energygroups)
+                            .map(|from| {
+                                (self.fission[from][to][cell] * previous[from][cell] / k)
+                                    + self.scattering[from][to][cell] * flux[from][cell]
+                            })
+                            .sum()
+                    })
+                    .collect();
+                flux[to] = self.thomas(to, &source);
+            }
+            let new_production = self.production(&flux);
+            let new_k = k * new_production / production;
+            // Largest relative change of the flux shape
+            let change = flux
+                .iter()
+                .flatten()
+                .zip(previous.iter().flatten())
+                .map(|(x, y)| (1.0 - y / production * new_production / x).abs())
+                .fold(0.0, f64::max);
+            let delta_k = ((new_k - k) / k).abs();
+            k = new_k;
+            production = new_production;
+            iterations += 1;
+            if delta_k < COARSE_TOLERANCE && change < COARSE_TOLERANCE.sqrt() {
+                break;
+            }
+        }
+
+        let norm = initial_production / production;
+        for phi in flux.iter_mut().flatten() {
+            *phi *= norm;
+        }
+        (flux, k, iterations)
+    }
+}
+
+// Solves the coarse mesh problem built from flux and scales the fine flux of every coarse cell
+// by the change of its average. Returns the coarse eigenvalue and the coarse iterations.
+pub fn cmfd_update(
+    flux: &mut [Vec<f32>],
+    k: f32,
+    a_matrix: &[TriDiagonal],
+    cells: &[(usize, usize)],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> (f32, usize) {
+    let coarse = CoarseProblem::new(flux, a_matrix, cells, xsdata, meshid, variables);
+    let (coarse_flux, coarse_k, iterations) = coarse.solve(k);
+    for g in 0..flux.len() {
+        for (cell, &(start, end)) in cells.iter().enumerate() {
+            let factor = (coarse_flux[g][cell] / coarse.flux[g][cell]) as f32;
+            for phi in flux[g][start..end].iter_mut() {
+                *phi *= factor;
+            }
+        }
+    }
+    (coarse_k as f32, iterations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cmfd::{coarse_cells, CoarseProblem};
+    use crate::discrete::tests::{library, slab_meshes, slab_variables};
+    use crate::discrete::TriDiagonal;
+    use crate::{CoarseMesh, Variables, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_coarse_balance() {
+        // One group, fuel and water pins with a uniform operator
+        let meshid = slab_meshes(8, 0.5, |i| (i / 2 % 2) as u8);
+        let variables = Variables {
+            numass: 2,
+            numrods: 2,
+            rodpitch: 2.0,
+            mpfr: 2,
+            mpwr: 2,
+            boundl: 0.0,
+            boundr: 0.0,
+            ..slab_variables(2, 1)
+        };
+        let xsdata = XSData {
+            sigt: vec![1.0, 1.0],
+            sigs: vec![0.9, 1.0],
+            siga: vec![0.1, 0.0],
+            sigf: vec![0.05, 0.0],
+            nut: vec![2.4, 0.0],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![0.9, 1.0],
+            inv_sigtr: vec![1.0, 1.0],
+            ..library(2)
+        };
+        let mut a = TriDiagonal::new(8);
+        for i in 0..8 {
+            a.row_sum[i] = 0.05;
+            if i > 0 {
+                a.lower[i] = -2.0;
+            }
+            if i < 7 {
+                a.upper[i] = -2.0;
+            }
+            a.diag[i] = a.row_sum[i] - a.lower[i] - a.upper[i];
+        }
+        let flux = vec![vec![1.0, 1.5, 2.2, 2.6, 2.7, 2.3, 1.6, 1.1]];
+        let fine_balance: Vec<f32> = (0..8)
+            .map(|i| a.diag[i] * flux[0][i] + a.off_diagonal(i, &flux[0]))
+            .collect();
+
+        let cells = coarse_cells(&meshid, &CoarseMesh::Pin, variables.numass);
+        assert_eq!(cells, vec![(0, 2), (2, 4), (4, 6), (6, 8)]);
+        assert_eq!(
+            coarse_cells(&meshid, &CoarseMesh::Assembly, variables.numass),
+            vec![(0, 4), (4, 8)]
+        );
+        // A leftover mesh belongs to the last assembly, as in the assembly averages
+        assert_eq!(
+            coarse_cells(&slab_meshes(9, 0.5, |_| 0), &CoarseMesh::Assembly, 2),
+            vec![(0, 4), (4, 9)]
+        );
+
+        // The coarse operator applied to the averaged flux gives the summed fine balance
+        let coarse = CoarseProblem::new(&flux, &[a], &cells, &xsdata, &meshid, &variables);
+        let phi = &coarse.flux[0];
+        for (cell, &(start, end)) in cells.iter().enumerate() {
+            let mut coarse_balance = coarse.diag[0][cell] * phi[cell];
+            if cell > 0 {
+                coarse_balance += coarse.lower[0][cell] * phi[cell - 1];
+            }
+            if cell + 1 < cells.len() {
+                coarse_balance += coarse.upper[0][cell] * phi[cell + 1];
+            }
+            let fine_balance: f64 = fine_balance[start..end].iter().map(|x| *x as f64).sum();
+            assert!(coarse_balance.approx_eq(fine_balance, (1e-5, 2)));
+        }
+    }
+}
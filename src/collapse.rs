@@ -0,0 +1,270 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::{Mesh, Variables, XSData};
+
+// Reaction rate preserving collapse of the library onto the coarse groups of mapping, which
+// holds the 1 based coarse group of every fine group. Each material is weighted by the flux
+// summed over its meshes and nu by the fission rate. The transport cross section is flux
+// weighted (consistent P1) or weighted by the magnitude of the diffusion current, mu is then
+// set so that sigt - mu sigs gives it back.
+pub fn energy_collapse(
+    flux: &[Vec<f32>],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    mapping: &[usize],
+    current_weighted: bool,
+) -> Result<XSData, Box<dyn Error>> {
+    let (groups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    let coarse = mapping.iter().copied().max().unwrap_or(0);
+    if mapping.len() != groups
+        || mapping.contains(&0)
+        || (1..=coarse).any(|c| !mapping.contains(&c))
+    {
+        return Err(format!(
+            "Collapse needs a coarse group, numbered from 1 up, for each of the {} groups",
+            groups
+        )
+        .into());
+    }
+    let fine = |c: usize| (0..groups).filter(move |g| mapping[*g] == c + 1);
+    let at = |field: &[f32], matid: usize, g: usize| field[matid + mattypes * g] as f64;
+
+    // Diffusion current of every group at the mesh centres, one sided at the edges
+    let n = meshid.len();
+    let current: Vec<Vec<f64>> = (0..groups)
+        .map(|g| {
+            (0..n)
+                .map(|x| {
+                    let (left, right) = (x.saturating_sub(1), (x + 1).min(n - 1));
+                    let centre = |mesh: &Mesh| 0.5 * (mesh.mesh_left + mesh.mesh_right) as f64;
+                    let distance = centre(&meshid[right]) - centre(&meshid[left]);
+                    if distance <= 0.0 {
+                        return 0.0;
+                    }
+                    let diffusion = at(&xsdata.inv_sigtr, meshid[x].matid as usize, g) / 3.0;
+                    (diffusion * (flux[g][right] - flux[g][left]) as f64 / distance).abs()
+                })
+                .collect()
+        })
+        .collect();
+
+    // Flux and current volume integrals of every material and fine group
+    let mut phi = vec![vec![0.0; groups]; mattypes];
+    let mut j = vec![vec![0.0; groups]; mattypes];
+    let mut volume = vec![0.0; mattypes];
+    for (x, mesh) in meshid.iter().enumerate() {
+        let (matid, dx) = (mesh.matid as usize, mesh.delta_x as f64);
+        volume[matid] += dx;
+        for g in 0..groups {
+            phi[matid][g] += flux[g][x] as f64 * dx;
+            j[matid][g] += current[g][x] * dx;
+        }
+    }
+
+    let mut collapsed = XSData {
+        sigt: vec![0.0; coarse * mattypes],
+        sigs: vec![0.0; coarse * mattypes],
+        sigs2: vec![0.0; coarse * mattypes],
+        sigs3: vec![0.0; coarse * mattypes],
+        mu: vec![0.0; coarse * mattypes],
+        siga: vec![0.0; coarse * mattypes],
+        sigf: vec![0.0; coarse * mattypes],
+        nut: vec![0.0; coarse * mattypes],
+        chit: vec![0.0; coarse * mattypes],
+        chid: vec![0.0; coarse * mattypes],
+        beta: xsdata.beta.clone(),
+        lambda: xsdata.lambda.clone(),
+        velocity: Vec::new(),
+        scat_matrix: vec![0.0; coarse * coarse * mattypes],
+        inv_sigtr: vec![0.0; coarse * mattypes],
+        source: Vec::new(),
+    };
+
+    for matid in 0..mattypes {
+        // A material that is not in the problem keeps a flat spectrum
+        let weight: Vec<f64> = match volume[matid] > 0.0 {
+            true => phi[matid].clone(),
+            false => vec![1.0; groups],
+        };
+        for c in 0..coarse {
+            let index = matid + mattypes * c;
+            let total: f64 = fine(c).map(|g| weight[g]).sum();
+            let average = |field: &[f32]| -> f32 {
+                (fine(c)
+                    .map(|g| at(field, matid, g) * weight[g])
+                    .sum::<f64>()
+                    / total) as f32
+            };
+            collapsed.sigt[index] = average(&xsdata.sigt);
+            collapsed.sigs[index] = average(&xsdata.sigs);
+            collapsed.sigs2[index] = average(&xsdata.sigs2);
+            collapsed.sigs3[index] = average(&xsdata.sigs3);
+            collapsed.siga[index] = average(&xsdata.siga);
+            collapsed.sigf[index] = average(&xsdata.sigf);
+            // chi of a material is the same in all its meshes, so the fission source weighted
+            // spectrum is the sum over the fine groups
+            let spectrum =
+                |field: &[f32]| -> f32 { fine(c).map(|g| at(field, matid, g)).sum::<f64>() as f32 };
+            collapsed.chit[index] = spectrum(&xsdata.chit);
+            collapsed.chid[index] = spectrum(&xsdata.chid);
+
+            let fission_rate: f64 = fine(c)
+                .map(|g| at(&xsdata.sigf, matid, g) * weight[g])
+                .sum();
+            if fission_rate > 0.0 {
+                collapsed.nut[index] = (fine(c)
+                    .map(|g| at(&xsdata.nut, matid, g) * at(&xsdata.sigf, matid, g) * weight[g])
+                    .sum::<f64>()
+                    / fission_rate) as f32;
+            }
+
+            for to in 0..coarse {
+                collapsed.scat_matrix[coarse.pow(2) * matid + coarse * c + to] = (fine(c)
+                    .flat_map(|g| fine(to).map(move |h| (g, h)))
+                    .map(|(g, h)| {
+                        xsdata.scat_matrix[groups.pow(2) * matid + groups * g + h] as f64
+                            * weight[g]
+                    })
+                    .sum::<f64>()
+                    / total)
+                    as f32;
+            }
+
+            // Transport cross section 1 / inv_sigtr with the chosen spectrum
+            let spectrum: Vec<f64> = match current_weighted && fine(c).any(|g| j[matid][g] > 0.0) {
+                true => j[matid].clone(),
+                false => weight.clone(),
+            };
+            let sigtr = fine(c)
+                .map(|g| spectrum[g] / at(&xsdata.inv_sigtr, matid, g))
+                .sum::<f64>()
+                / fine(c).map(|g| spectrum[g]).sum::<f64>();
+            collapsed.inv_sigtr[index] = (1.0 / sigtr) as f32;
+            if collapsed.sigs[index] > 0.0 {
+                collapsed.mu[index] =
+                    ((collapsed.sigt[index] as f64 - sigtr) / collapsed.sigs[index] as f64) as f32;
+            }
+        }
+    }
+
+    // Group wise data: 1 / v weighted by the whole problem flux, the external source summed
+    let problem: Vec<f64> = (0..groups)
+        .map(|g| (0..mattypes).map(|matid| phi[matid][g]).sum())
+        .collect();
+    if xsdata.velocity.len() == groups {
+        collapsed.velocity = (0..coarse)
+            .map(|c| {
+                let inverse: f64 = fine(c)
+                    .map(|g| problem[g] / xsdata.velocity[g] as f64)
+                    .sum::<f64>()
+                    / fine(c).map(|g| problem[g]).sum::<f64>();
+                (1.0 / inverse) as f32
+            })
+            .collect();
+    }
+    if xsdata.source.len() == groups * mattypes {
+        collapsed.source = (0..coarse)
+            .flat_map(|c| {
+                (0..mattypes).map(move |matid| {
+                    fine(c).map(|g| at(&xsdata.source, matid, g)).sum::<f64>() as f32
+                })
+            })
+            .collect();
+    }
+    Ok(collapsed)
+}
+
+// Writes the collapsed library in the input deck format, to replace EnergyGroups and the
+// XSData section of a deck
+pub fn write_library(
+    xsdata: &XSData,
+    mattypes: usize,
+    groups: usize,
+    path: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let line = |values: &[f32]| -> String {
+        values
+            .iter()
+            .map(|x| format!("{:>12.6e}", x))
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+    writeln!(file, "# Cross sections, {} groups #", groups)?;
+    writeln!(file, "EnergyGroups = {}", groups)?;
+    writeln!(file)?;
+    writeln!(file, "XSData")?;
+    for (key, field) in [
+        ("SigT ", &xsdata.sigt),
+        ("SigS ", &xsdata.sigs),
+        ("SigS2", &xsdata.sigs2),
+        ("SigS3", &xsdata.sigs3),
+        ("mu   ", &xsdata.mu),
+        ("SigA ", &xsdata.siga),
+        ("SigF ", &xsdata.sigf),
+        ("nuT  ", &xsdata.nut),
+        ("ChiT ", &xsdata.chit),
+        ("ChiD ", &xsdata.chid),
+        ("Source", &xsdata.source),
+    ] {
+        for values in field.chunks(mattypes) {
+            writeln!(file, "    {} = {}", key, line(values))?;
+        }
+    }
+    for (key, field) in [
+        ("Beta    ", &xsdata.beta),
+        ("Lambda  ", &xsdata.lambda),
+        ("Velocity", &xsdata.velocity),
+    ] {
+        if !field.is_empty() {
+            writeln!(file, "    {} = {}", key, line(field))?;
+        }
+    }
+    writeln!(file)?;
+    for values in xsdata.scat_matrix.chunks(groups * groups) {
+        writeln!(file, "    Scat = {}", line(values))?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collapse::energy_collapse;
+    use crate::discrete::tests::two_group_medium;
+    use crate::XSData;
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_infinite_medium_collapse() {
+        // With the infinite medium spectrum phi = (1 / 0.03, 0.02 / (0.03 * 0.1)) the one group
+        // constants keep the reaction rates, so k = nu sigf / (sigt - sigs11) stays 7 / 6
+        let (xsdata, meshid, variables) = two_group_medium();
+        let xsdata = XSData {
+            chid: vec![1.0, 0.0],
+            velocity: vec![1e7, 2.2e5],
+            ..xsdata
+        };
+        let flux = vec![vec![1.0 / 0.03; 10], vec![0.02 / 0.003; 10]];
+
+        let collapsed =
+            energy_collapse(&flux, &xsdata, &meshid, &variables, &[1, 1], false).unwrap();
+        let k =
+            collapsed.nut[0] * collapsed.sigf[0] / (collapsed.sigt[0] - collapsed.scat_matrix[0]);
+        assert!(k.approx_eq(7.0 / 6.0, (1e-5, 2)));
+        assert!(collapsed.chit[0].approx_eq(1.0, (1e-6, 2)));
+        // The slowing down flux ratio is 1 : 0.2, 1 / v is flux weighted
+        let inverse: f32 = (1.0 / 1e7 + 0.2 / 2.2e5) / 1.2;
+        assert!((1.0 / collapsed.velocity[0]).approx_eq(inverse, (1e-12, 2)));
+
+        // The identity mapping gives the library back
+        let same = energy_collapse(&flux, &xsdata, &meshid, &variables, &[1, 2], true).unwrap();
+        for (x, y) in same.scat_matrix.iter().zip(xsdata.scat_matrix.iter()) {
+            assert!(x.approx_eq(*y, (1e-6, 2)));
+        }
+        assert!(energy_collapse(&flux, &xsdata, &meshid, &variables, &[1, 3], false).is_err());
+    }
+}
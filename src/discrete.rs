@@ -1,144 +1,187 @@
-use crate::{Mesh, SolutionResults, XSData};
-use nalgebra::*;
+use nalgebra::{DMatrix, DVector};
+use std::error::Error;
+use std::ops::Range;
+
+use crate::cmfd::{cmfd_update, coarse_cells};
+use crate::multigrid::Multigrid;
+use crate::sparse::{bicgstab, conjugate_gradient, gmres, CsrMatrix, Preconditioning};
+use crate::{Acceleration, Mesh, SolutionResults, Solver, SolverSettings, Variables, XSData};
+
+// Limit of the group sweeps converging the shifted fission source within one Wielandt outer
+const WIELANDT_SWEEPS: usize = 50;
+// Number of plain power iterations used to estimate the dominance ratio
+const CHEBYSHEV_START: usize = 5;
+// Length of a Chebyshev cycle before the polynomial is restarted
+const CHEBYSHEV_CYCLE: usize = 6;
+// Successive source iterations with a growing fission source before the system is taken to be
+// supercritical
+const SUPERCRITICAL_ITERATIONS: usize = 5;
+
+// Banded storage of the three point diffusion operator. lower[0] and upper[n - 1] fall outside
+// of the matrix and are kept at zero so every row can be treated the same.
+// row_sum holds the removal and boundary leakage of each row. On fine meshes these are lost
+// to round off in diag - |lower| - |upper|, so they are stored separately.
+pub struct TriDiagonal {
+    pub lower: Vec<f32>,
+    pub diag: Vec<f32>,
+    pub upper: Vec<f32>,
+    pub row_sum: Vec<f32>,
+}
+
+impl TriDiagonal {
+    pub fn new(n: usize) -> Self {
+        Self {
+            lower: vec![0.0; n],
+            diag: vec![0.0; n],
+            upper: vec![0.0; n],
+            row_sum: vec![0.0; n],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.diag.len()
+    }
+
+    // Sum of the off diagonal terms of row i applied to x
+    #[inline(always)]
+    pub fn off_diagonal(&self, i: usize, x: &[f32]) -> f32 {
+        let mut sum = 0.0;
+        if i > 0 {
+            sum += self.lower[i] * x[i - 1];
+        }
+        if i + 1 < self.len() {
+            sum += self.upper[i] * x[i + 1];
+        }
+        sum
+    }
+}
+
+// Current out of the problem per flux of the edge mesh. The Marshak condition
+// J = (1 - albedo) / (2 (1 + albedo)) phi_s on the face and J = 2 d (phi - phi_s) across the half
+// mesh, with d = D / delta_x, give J = 2 d c / (2 d + c) phi.
+pub fn boundary_coupling(albedo: f32, d: f32) -> f32 {
+    let c = 0.5 * (1.0 - albedo) / (1.0 + albedo);
+    2.0 * d * c / (2.0 * d + c)
+}
 
 fn matrix_gen(
-    n: usize,
     xsdata: &XSData,
-    meshid: &Vec<Mesh>,
+    meshid: &[Mesh],
+    variables: &Variables,
     neutron_energy: usize,
-    mattypes: u8,
-    boundl: f32,
-    boundr: f32,
-    energygroups: u8,
-) -> Vec<Vec<f32>> {
+) -> TriDiagonal {
+    let n = meshid.len();
+    let (mattypes, energygroups) = (variables.mattypes as usize, variables.energygroups as usize);
+    let (boundl, boundr) = (variables.boundl, variables.boundr);
     // Generate the matrix A
-    let mut a = vec![vec![0.0; n]; n];
+    let mut a = TriDiagonal::new(n);
 
     // Set values for original insertion
     let d_curr: f32 = 3.0_f32.powi(-1)
-        * xsdata.inv_sigtr[(meshid[0].matid + (mattypes * neutron_energy as u8)) as usize]
+        * xsdata.inv_sigtr[meshid[0].matid as usize + mattypes * neutron_energy]
         * meshid[0].delta_x.powi(-1);
     let d_next: f32 = 3.0_f32.powi(-1)
-        * xsdata.inv_sigtr[(meshid[1].matid + (mattypes * neutron_energy as u8)) as usize]
+        * xsdata.inv_sigtr[meshid[1].matid as usize + mattypes * neutron_energy]
         * meshid[1].delta_x.powi(-1);
     let d_nextcurr: f32 = (2.0 * d_curr * d_next) * (d_curr + d_next).powi(-1);
 
-    let beta_l: f32 = match boundl {
-        x if x == 1.0 => 1.0,
-        x if x == 0.0 => 0.25,
-        _ => {
-            (1.0 - 0.25 * ((1.0 - boundl) / (1.0 + boundl) * (1.0 / d_next)))
-                / (1.0 + 0.25 * ((1.0 - boundl) / (1.0 + boundl) * (1.0 / d_curr)))
-        }
-    };
-
     // Insert 0,0 and n,n since these differ from the pattern
-    a[0][0] = 2.0 * d_curr * (1.0 - beta_l)
-        + meshid[0].delta_x
-            * (xsdata.sigt[(meshid[0].matid + (mattypes * neutron_energy as u8)) as usize]
-                - xsdata.scat_matrix[(((energygroups + 1) * neutron_energy as u8)
-                    + (energygroups.pow(2) * meshid[0].matid))
-                    as usize])
-        + d_nextcurr;
+    let removal: f32 = meshid[0].delta_x
+        * (xsdata.sigt[meshid[0].matid as usize + mattypes * neutron_energy]
+            - xsdata.scat_matrix[(energygroups + 1) * neutron_energy
+                + energygroups.pow(2) * meshid[0].matid as usize]);
+    a.diag[0] = boundary_coupling(boundl, d_curr) + removal + d_nextcurr;
+    a.row_sum[0] = boundary_coupling(boundl, d_curr) + removal;
 
-    a[0][1] = -d_nextcurr;
+    a.upper[0] = -d_nextcurr;
 
     for x in 1..n - 1 {
         let d_curr: f32 = 3.0_f32.powi(-1)
-            * xsdata.inv_sigtr[(meshid[x].matid + (mattypes * neutron_energy as u8)) as usize]
+            * xsdata.inv_sigtr[meshid[x].matid as usize + mattypes * neutron_energy]
             * meshid[x].delta_x.powi(-1);
         let d_prev: f32 = 3.0_f32.powi(-1)
-            * xsdata.inv_sigtr[(meshid[x - 1].matid + (mattypes * neutron_energy as u8)) as usize]
+            * xsdata.inv_sigtr[meshid[x - 1].matid as usize + mattypes * neutron_energy]
             * meshid[x - 1].delta_x.powi(-1);
         let d_next: f32 = 3.0_f32.powi(-1)
-            * xsdata.inv_sigtr[(meshid[x + 1].matid + (mattypes * neutron_energy as u8)) as usize]
+            * xsdata.inv_sigtr[meshid[x + 1].matid as usize + mattypes * neutron_energy]
             * meshid[x + 1].delta_x.powi(-1);
 
         let d_prevcurr: f32 = (2.0 * d_curr * d_prev) * (d_curr + d_prev).powi(-1);
         let d_nextcurr: f32 = (2.0 * d_curr * d_next) * (d_curr + d_next).powi(-1);
 
-        a[x][x - 1] = -d_prevcurr;
-        a[x][x] = d_prevcurr
-            + meshid[x].delta_x
-                * (xsdata.sigt[(meshid[x].matid + (mattypes * neutron_energy as u8)) as usize]
-                    - xsdata.scat_matrix[(((energygroups + 1) * neutron_energy as u8)
-                        + (energygroups.pow(2) * meshid[x].matid))
-                        as usize])
-            + d_nextcurr;
-        a[x][x + 1] = -d_nextcurr;
+        let removal: f32 = meshid[x].delta_x
+            * (xsdata.sigt[meshid[x].matid as usize + mattypes * neutron_energy]
+                - xsdata.scat_matrix[(energygroups + 1) * neutron_energy
+                    + energygroups.pow(2) * meshid[x].matid as usize]);
+
+        a.lower[x] = -d_prevcurr;
+        a.diag[x] = d_prevcurr + removal + d_nextcurr;
+        a.upper[x] = -d_nextcurr;
+        a.row_sum[x] = removal;
     }
 
     // Set values for end insertion
     let d_curr: f32 = 3.0_f32.powi(-1)
-        * xsdata.inv_sigtr[(meshid[n - 1].matid + (mattypes * neutron_energy as u8)) as usize]
+        * xsdata.inv_sigtr[meshid[n - 1].matid as usize + mattypes * neutron_energy]
         / meshid[n - 1].delta_x;
     let d_prev: f32 = 3.0_f32.powi(-1)
-        * xsdata.inv_sigtr[(meshid[n - 2].matid + (mattypes * neutron_energy as u8)) as usize]
+        * xsdata.inv_sigtr[meshid[n - 2].matid as usize + mattypes * neutron_energy]
         / meshid[n - 2].delta_x;
     let d_prevcurr: f32 = (2.0 * d_curr * d_prev) / (d_curr + d_prev);
 
-    // I feel like this shouldn't use d_next
-    let beta_r = match boundr {
-        x if x == 1.0 => 1.0,
-        x if x == 0.0 => 0.25,
-        _ => {
-            (1.0 - 0.25 * ((1.0 - boundr) / (1.0 + boundr) * (1.0 / d_next)))
-                / (1.0 + 0.25 * ((1.0 - boundr) / (1.0 + boundr) * (1.0 / d_curr)))
-        }
-    };
-
     // [(mattype * energygroups) + ((energygroups * starting_energy) + final_energy)]
 
-    a[n - 1][n - 2] = -d_prevcurr;
-    a[n - 1][n - 1] = 2.0 * d_curr * (1.0 - beta_r)
-        + meshid[n - 1].delta_x
-            * (xsdata.sigt[(meshid[n - 1].matid + (mattypes * neutron_energy as u8)) as usize]
-                - xsdata.scat_matrix[(((energygroups + 1) * neutron_energy as u8)
-                    + (energygroups.pow(2) * meshid[n - 1].matid))
-                    as usize])
-        + d_nextcurr;
+    let removal: f32 = meshid[n - 1].delta_x
+        * (xsdata.sigt[meshid[n - 1].matid as usize + mattypes * neutron_energy]
+            - xsdata.scat_matrix[(energygroups + 1) * neutron_energy
+                + energygroups.pow(2) * meshid[n - 1].matid as usize]);
+
+    a.lower[n - 1] = -d_prevcurr;
+    a.diag[n - 1] = boundary_coupling(boundr, d_curr) + removal + d_prevcurr;
+    a.row_sum[n - 1] = boundary_coupling(boundr, d_curr) + removal;
     a
 }
 
-fn q_gen(
+pub fn q_gen(
     xsdata: &XSData,
     energygroups: u8,
     mattypes: u8,
-    flux: &Vec<Vec<f32>>,
-    meshid: &Vec<Mesh>,
+    flux: &[Vec<f32>],
+    meshid: &[Mesh],
 ) -> Vec<Vec<f32>> {
     let mut q: Vec<Vec<f32>> = vec![vec![0.0; meshid.len()]; energygroups as usize];
     for neutron_energy in 0..energygroups {
         for index in 0..meshid.len() {
             q[neutron_energy as usize][index] = (0..energygroups)
-                .into_iter()
                 .map(|x| {
-                    xsdata.nut[(meshid[index].matid + (mattypes * x)) as usize]
-                        * xsdata.sigf[(meshid[index].matid + (mattypes * x)) as usize]
+                    xsdata.nut[meshid[index].matid as usize + mattypes as usize * x as usize]
+                        * xsdata.sigf[meshid[index].matid as usize + mattypes as usize * x as usize]
                         * flux[x as usize][index]
                 })
                 .sum::<f32>()
                 * meshid[index].delta_x
-                * xsdata.chit[(meshid[index].matid + (mattypes * neutron_energy)) as usize];
+                * xsdata.chit
+                    [meshid[index].matid as usize + mattypes as usize * neutron_energy as usize];
         }
     }
     q
 }
 
-fn scat_calc(
+pub fn scat_calc(
     index: usize,
     xsdata: &XSData,
-    meshid: &Vec<Mesh>,
-    flux: &Vec<Vec<f32>>,
+    meshid: &[Mesh],
+    flux: &[Vec<f32>],
     neutron_energy: usize,
     energygroups: u8,
 ) -> f32 {
     let mut scat = 0.0;
     for energy in 0..energygroups {
         if energy != neutron_energy as u8 {
-            scat += xsdata.scat_matrix[((energygroups.pow(2) * meshid[index].matid)
-                + (energygroups * energy)
-                + neutron_energy as u8) as usize]
+            scat += xsdata.scat_matrix[(energygroups as usize).pow(2)
+                * meshid[index].matid as usize
+                + energygroups as usize * energy as usize
+                + neutron_energy]
                 * flux[energy as usize][index]
                 * meshid[index].delta_x;
         } else {
@@ -148,17 +191,202 @@ fn scat_calc(
     scat
 }
 
-fn average_assembly(flux: Vec<Vec<f32>>, numass: u8, energygroups: u8) -> Vec<Vec<f32>> {
-    let mesh_assembly = flux[0].len() / numass as usize;
+// Adjoint fission source, the production of every group weighted by the adjoint flux over the
+// emission spectrum
+pub fn q_gen_adjoint(
+    xsdata: &XSData,
+    energygroups: u8,
+    mattypes: u8,
+    adjoint: &[Vec<f32>],
+    meshid: &[Mesh],
+) -> Vec<Vec<f32>> {
+    let mut q: Vec<Vec<f32>> = vec![vec![0.0; meshid.len()]; energygroups as usize];
+    for neutron_energy in 0..energygroups {
+        for index in 0..meshid.len() {
+            q[neutron_energy as usize][index] = (0..energygroups)
+                .map(|x| {
+                    xsdata.chit[meshid[index].matid as usize + mattypes as usize * x as usize]
+                        * adjoint[x as usize][index]
+                })
+                .sum::<f32>()
+                * meshid[index].delta_x
+                * xsdata.nut
+                    [meshid[index].matid as usize + mattypes as usize * neutron_energy as usize]
+                * xsdata.sigf
+                    [meshid[index].matid as usize + mattypes as usize * neutron_energy as usize];
+        }
+    }
+    q
+}
+
+// Adjoint scattering source, the transfers out of neutron_energy weighted by the adjoint flux
+fn scat_calc_adjoint(
+    index: usize,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    adjoint: &[Vec<f32>],
+    neutron_energy: usize,
+    energygroups: u8,
+) -> f32 {
+    let mut scat = 0.0;
+    for energy in 0..energygroups {
+        if energy != neutron_energy as u8 {
+            scat += xsdata.scat_matrix[(energygroups as usize).pow(2)
+                * meshid[index].matid as usize
+                + energygroups as usize * neutron_energy
+                + energy as usize]
+                * adjoint[energy as usize][index]
+                * meshid[index].delta_x;
+        }
+    }
+    scat
+}
+
+// Mean reduction of the iterate change per iteration, used to compare the iterative solvers
+fn convergence_rate(first_delta: f32, last_delta: f32, iterations: usize) -> f32 {
+    if iterations > 1 && first_delta > 0.0 {
+        (last_delta / first_delta).powf(1.0 / (iterations - 1) as f32)
+    } else {
+        0.0
+    }
+}
+
+// Thomas algorithm, Gaussian elimination specialised to the tridiagonal operator.
+// The reduced pivots are carried as e = pivot + upper, which only ever adds positive terms
+// for the diffusion operator, so the elimination keeps the removal terms in row_sum.
+pub fn thomas(a: &TriDiagonal, b: &[f32]) -> Vec<f32> {
+    let n = a.len();
+    let mut pivot = vec![0.0; n];
+    let mut x = vec![0.0; n];
+
+    let mut e: f32 = a.row_sum[0];
+    pivot[0] = e - a.upper[0];
+    x[0] = b[0] / pivot[0];
+    for i in 1..n {
+        e = a.row_sum[i] - a.lower[i] * e / pivot[i - 1];
+        pivot[i] = e - a.upper[i];
+        x[i] = (b[i] - a.lower[i] * x[i - 1]) / pivot[i];
+    }
+    for i in (0..n - 1).rev() {
+        x[i] -= a.upper[i] / pivot[i] * x[i + 1];
+    }
+    x
+}
+
+// Jacobi iteration on the group operator, starting from the previous outer's flux
+fn jacobi(
+    a: &TriDiagonal,
+    b: &[f32],
+    guess: &[f32],
+    tolerance: f32,
+    max_iterations: usize,
+) -> (Vec<f32>, usize, f32) {
+    let n = b.len();
+    let mut x = guess.to_vec();
+    let mut x_new = vec![0.0; n];
+    let mut first_delta: f32 = 0.0;
+
+    for iteration in 1..=max_iterations {
+        let mut delta: f32 = 0.0;
+        for i in 0..n {
+            x_new[i] = (b[i] - a.off_diagonal(i, &x)) / a.diag[i];
+            delta = delta.max(((x_new[i] - x[i]) / x_new[i]).abs());
+        }
+        std::mem::swap(&mut x, &mut x_new);
+        if iteration == 1 {
+            first_delta = delta;
+        }
+        if delta < tolerance || iteration == max_iterations {
+            return (
+                x,
+                iteration,
+                convergence_rate(first_delta, delta, iteration),
+            );
+        }
+    }
+    (x, max_iterations, 0.0)
+}
+
+// Successive over-relaxation, a Gauss-Seidel sweep extrapolated by omega
+fn sor(
+    a: &TriDiagonal,
+    b: &[f32],
+    guess: &[f32],
+    omega: f32,
+    tolerance: f32,
+    max_iterations: usize,
+) -> (Vec<f32>, usize, f32) {
+    let n = b.len();
+    let mut x = guess.to_vec();
+    let mut first_delta: f32 = 0.0;
+
+    for iteration in 1..=max_iterations {
+        let mut delta: f32 = 0.0;
+        for i in 0..n {
+            let temp = (1.0 - omega) * x[i] + omega * (b[i] - a.off_diagonal(i, &x)) / a.diag[i];
+            delta = delta.max(((temp - x[i]) / temp).abs());
+            x[i] = temp;
+        }
+        if iteration == 1 {
+            first_delta = delta;
+        }
+        if delta < tolerance || iteration == max_iterations {
+            return (
+                x,
+                iteration,
+                convergence_rate(first_delta, delta, iteration),
+            );
+        }
+    }
+    (x, max_iterations, 0.0)
+}
+
+// Power iteration on the Jacobi iteration matrix D^-1 (L + U). Its eigenvalues come in +/- pairs
+// for the three point stencil, so two applications are used per estimate.
+fn jacobi_spectral_radius(a: &TriDiagonal, max_iterations: usize) -> f32 {
+    let n = a.len();
+    let apply =
+        |v: &[f32]| -> Vec<f32> { (0..n).map(|i| -a.off_diagonal(i, v) / a.diag[i]).collect() };
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    let mut v = vec![1.0; n];
+    let mut rho: f32 = 0.0;
+    for _ in 0..max_iterations {
+        let v_next = apply(&apply(&v));
+        let temp = (norm(&v_next) / norm(&v)).sqrt();
+        let scale = norm(&v_next);
+        v = v_next.iter().map(|x| x / scale).collect();
+        if (temp - rho).abs() < 1e-7 {
+            return temp;
+        }
+        rho = temp;
+    }
+    rho
+}
+
+// Optimal over-relaxation factor for a consistently ordered matrix
+fn optimal_omega(rho: f32) -> f32 {
+    2.0 / (1.0 + (1.0 - rho.powi(2).min(1.0)).sqrt())
+}
+
+// Meshes of every assembly, an equal number each with any leftover meshes in the last one
+pub fn assembly_ranges(meshes: usize, numass: u8) -> Vec<Range<usize>> {
+    let mesh_assembly = meshes / numass as usize;
+    (0..numass as usize)
+        .map(|assembly| match assembly + 1 == numass as usize {
+            true => assembly * mesh_assembly..meshes,
+            false => assembly * mesh_assembly..(assembly + 1) * mesh_assembly,
+        })
+        .collect()
+}
+
+pub fn average_assembly(flux: Vec<Vec<f32>>, numass: u8, energygroups: u8) -> Vec<Vec<f32>> {
     let mut average = vec![vec![0.0; flux[0].len()]; energygroups as usize];
     for energy in 0..energygroups as usize {
-        for assembly in 1..=numass as usize {
-            let average_ass: f32 = (((assembly - 1) * mesh_assembly)..(assembly * mesh_assembly))
-                .into_iter()
-                .map(|x| flux[energy][x])
-                .sum::<f32>()
-                / mesh_assembly as f32;
-            for index in ((assembly - 1) * mesh_assembly)..(assembly * mesh_assembly) {
+        for range in assembly_ranges(flux[0].len(), numass) {
+            let average_ass: f32 =
+                range.clone().map(|x| flux[energy][x]).sum::<f32>() / range.len() as f32;
+            for index in range {
                 average[energy][index] = average_ass;
             }
         }
@@ -166,145 +394,435 @@ fn average_assembly(flux: Vec<Vec<f32>>, numass: u8, energygroups: u8) -> Vec<Ve
     average
 }
 
-pub fn nalgebra_method(
+// The operator of every energy group along with whatever the selected inner solver needs
+pub struct InnerSolver<'a> {
+    settings: &'a SolverSettings,
+    pub a_matrix: Vec<TriDiagonal>,
+    csr_matrix: Vec<CsrMatrix>,
+    preconditioners: Vec<Preconditioning>,
+    multigrids: Vec<Multigrid>,
+    omegas: Vec<f32>,
+}
+
+impl<'a> InnerSolver<'a> {
+    // The estimated relaxation factors and the multigrid depth are printed when verbose
+    pub fn new(a_matrix: Vec<TriDiagonal>, settings: &'a SolverSettings, verbose: bool) -> Self {
+        // The Krylov solvers work on the operator in compressed sparse row form
+        let (csr_matrix, preconditioners): (Vec<CsrMatrix>, Vec<Preconditioning>) =
+            match settings.solver {
+                Solver::ConjugateGradient | Solver::BiCGStab | Solver::Gmres => a_matrix
+                    .iter()
+                    .map(|a| {
+                        let csr = CsrMatrix::from(a);
+                        let preconditioner = Preconditioning::new(&settings.preconditioner, &csr);
+                        (csr, preconditioner)
+                    })
+                    .unzip(),
+                _ => (Vec::new(), Vec::new()),
+            };
+
+        let multigrids: Vec<Multigrid> = match settings.solver {
+            Solver::Multigrid => {
+                let multigrids: Vec<Multigrid> = a_matrix.iter().map(Multigrid::new).collect();
+                if verbose {
+                    println!("multigrid hierarchy of {} levels", multigrids[0].depth());
+                }
+                multigrids
+            }
+            _ => Vec::new(),
+        };
+
+        // The relaxation factor comes from the input, otherwise it is estimated for each group
+        let omegas: Vec<f32> = match settings.solver {
+            Solver::SR => a_matrix
+                .iter()
+                .enumerate()
+                .map(|(neutron_energy, a)| {
+                    if settings.omega > 0.0 {
+                        settings.omega
+                    } else {
+                        let rho = jacobi_spectral_radius(a, settings.max_inner);
+                        let omega = optimal_omega(rho);
+                        if verbose {
+                            println!(
+                                "group {}: Jacobi spectral radius {:.6}, omega {:.6}",
+                                neutron_energy + 1,
+                                rho,
+                                omega
+                            );
+                        }
+                        omega
+                    }
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        InnerSolver {
+            settings,
+            a_matrix,
+            csr_matrix,
+            preconditioners,
+            multigrids,
+            omegas,
+        }
+    }
+
+    pub fn is_iterative(&self) -> bool {
+        !matches!(self.settings.solver, Solver::LinAlg | Solver::Gaussian)
+    }
+
+    // Solves the group operator against source, returning the flux, the number of inner
+    // iterations and their mean convergence rate
+    pub fn solve(
+        &self,
+        neutron_energy: usize,
+        source: &[f32],
+        guess: &[f32],
+    ) -> (Vec<f32>, usize, f32) {
+        let settings = self.settings;
+        match settings.solver {
+            Solver::Jacobian => jacobi(
+                &self.a_matrix[neutron_energy],
+                source,
+                guess,
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::SR => sor(
+                &self.a_matrix[neutron_energy],
+                source,
+                guess,
+                self.omegas[neutron_energy],
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::ConjugateGradient => conjugate_gradient(
+                &self.csr_matrix[neutron_energy],
+                &self.preconditioners[neutron_energy],
+                source,
+                guess,
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::BiCGStab => bicgstab(
+                &self.csr_matrix[neutron_energy],
+                &self.preconditioners[neutron_energy],
+                source,
+                guess,
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::Gmres => gmres(
+                &self.csr_matrix[neutron_energy],
+                &self.preconditioners[neutron_energy],
+                source,
+                guess,
+                settings.restart,
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::Multigrid => self.multigrids[neutron_energy].solve(
+                source,
+                guess,
+                settings.cycle,
+                settings.inner_tolerance,
+                settings.max_inner,
+            ),
+            Solver::LinAlg | Solver::Gaussian => {
+                (thomas(&self.a_matrix[neutron_energy], source), 0, 0.0)
+            }
+        }
+    }
+}
+
+pub fn group_operators(
     xsdata: &XSData,
-    meshid: &Vec<Mesh>,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> Vec<TriDiagonal> {
+    (0..variables.energygroups as usize)
+        .map(|neutron_energy| matrix_gen(xsdata, meshid, variables, neutron_energy))
+        .collect()
+}
+
+// Solves one group against its fission source, already divided by the eigenvalue, plus the
+// scattering from the latest flux of every other group. Returns the inner iterations and rate.
+#[allow(clippy::too_many_arguments)]
+fn solve_group(
+    flux: &mut [Vec<f32>],
+    fission: &[Vec<f32>],
+    inner: &InnerSolver,
+    xsdata: &XSData,
+    meshid: &[Mesh],
     energygroups: u8,
-    mattypes: u8,
-    boundl: f32,
-    boundr: f32,
-    numass: u8,
-) -> SolutionResults {
-    let n: usize = meshid.len();
-    let mut flux: Vec<Vec<f32>> = vec![vec![1.0; n]; energygroups as usize];
-    let mut q: Vec<Vec<f32>> = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
-    let (mut k, mut delta_flux, mut delta_k): (f32, f32, f32) = (1.0, 1.0, 1.0);
+    neutron_energy: usize,
+    adjoint: bool,
+) -> (usize, f32) {
+    let source: Vec<f32> = (0..meshid.len())
+        .map(|index| {
+            fission[neutron_energy][index]
+                + match adjoint {
+                    true => {
+                        scat_calc_adjoint(index, xsdata, meshid, flux, neutron_energy, energygroups)
+                    }
+                    false => scat_calc(index, xsdata, meshid, flux, neutron_energy, energygroups),
+                }
+        })
+        .collect();
+
+    let (temp_flux, iterations, rate) = inner.solve(neutron_energy, &source, &flux[neutron_energy]);
+    flux[neutron_energy] = temp_flux;
+    (iterations, rate)
+}
 
-    let mut a_inv_matrix: Vec<Vec<Vec<f32>>> = Vec::with_capacity(energygroups as usize * n * n);
+// One Gauss-Seidel pass over the energy groups. fission[g] is the fission source of group g,
+// already divided by the eigenvalue. The scattering source uses the latest flux of every group.
+// The adjoint pass uses the transposed scattering and runs from the thermal group up.
+pub fn group_sweep(
+    flux: &mut [Vec<f32>],
+    fission: &[Vec<f32>],
+    inner: &InnerSolver,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    energygroups: u8,
+    adjoint: bool,
+) -> (Vec<usize>, Vec<f32>) {
+    let mut inner_iterations: Vec<usize> = vec![0; energygroups as usize];
+    let mut inner_rates: Vec<f32> = vec![0.0; energygroups as usize];
 
-    for neutron_energy in 0..energygroups as usize {
-        let temp_a = matrix_gen(
-            n,
+    let order: Vec<usize> = match adjoint {
+        true => (0..energygroups as usize).rev().collect(),
+        false => (0..energygroups as usize).collect(),
+    };
+    for neutron_energy in order {
+        (
+            inner_iterations[neutron_energy],
+            inner_rates[neutron_energy],
+        ) = solve_group(
+            flux,
+            fission,
+            inner,
             xsdata,
             meshid,
-            neutron_energy,
-            mattypes,
-            boundl,
-            boundr,
             energygroups,
+            neutron_energy,
+            adjoint,
         );
-        let a = DMatrix::from_vec(
-            temp_a.len(),
-            temp_a.len(),
-            temp_a.into_iter().flatten().collect::<Vec<f32>>(),
-        );
-        let a_inv = a.try_inverse().unwrap();
-        let temp_inv: Vec<f32> = a_inv.data.as_vec().to_owned();
-        let temp_vec: Vec<Vec<f32>> = temp_inv.chunks(n).map(|x| x.to_owned()).collect();
-        a_inv_matrix.push(temp_vec);
     }
+    (inner_iterations, inner_rates)
+}
 
-    while delta_flux >= 1e-5 && delta_k >= 1e-6 {
-        let temp_q = q.clone();
+// First group that receives upscattering in any material. The groups from it on form the
+// thermal block. Without upscattering this is the number of groups.
+pub fn thermal_group(xsdata: &XSData, energygroups: u8, mattypes: u8) -> usize {
+    let groups = energygroups as usize;
+    (0..groups)
+        .find(|to| {
+            (0..mattypes as usize).any(|matid| {
+                (to + 1..groups).any(|from| {
+                    xsdata.scat_matrix[groups.pow(2) * matid + groups * from + to] > 0.0
+                })
+            })
+        })
+        .unwrap_or(groups)
+}
 
-        for neutron_energy in 0..energygroups as usize {
-            let mut scat = Vec::with_capacity(n);
+// Scales the flux of every thermal group so the thermal block is in balance summed over the
+// whole problem. The factors f_g solve
+//     f_g sum(A_g phi_g) - sum_g' f_g' S(g' -> g) = fission_g + fast scattering into g
+// over the groups g, g' of the block.
+fn thermal_rebalance(
+    flux: &mut [Vec<f32>],
+    fission: &[Vec<f32>],
+    a_matrix: &[TriDiagonal],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    energygroups: u8,
+    thermal: usize,
+) {
+    let groups = energygroups as usize;
+    let size = groups - thermal;
+    let mut balance = DMatrix::<f64>::zeros(size, size);
+    let mut source = DVector::<f64>::zeros(size);
+    for g in thermal..groups {
+        let (a, phi) = (&a_matrix[g], &flux[g]);
+        // Removal plus leakage, from the row sums so it is kept on fine meshes
+        balance[(g - thermal, g - thermal)] = (0..a.len())
+            .map(|i| {
+                let mut row = a.row_sum[i] as f64 * phi[i] as f64;
+                if i > 0 {
+                    row += a.lower[i] as f64 * (phi[i - 1] - phi[i]) as f64;
+                }
+                if i + 1 < a.len() {
+                    row += a.upper[i] as f64 * (phi[i + 1] - phi[i]) as f64;
+                }
+                row
+            })
+            .sum();
+        source[g - thermal] = fission[g].iter().map(|x| *x as f64).sum();
+        for from in (0..groups).filter(|from| *from != g) {
+            let scattering: f64 = meshid
+                .iter()
+                .enumerate()
+                .map(|(index, mesh)| {
+                    (xsdata.scat_matrix[groups.pow(2) * mesh.matid as usize + groups * from + g]
+                        * flux[from][index]
+                        * mesh.delta_x) as f64
+                })
+                .sum();
+            match from < thermal {
+                true => source[g - thermal] += scattering,
+                false => balance[(g - thermal, from - thermal)] -= scattering,
+            }
+        }
+    }
+    if let Some(factors) = balance.lu().solve(&source) {
+        for g in thermal..groups {
+            for value in flux[g].iter_mut() {
+                *value *= factors[g - thermal] as f32;
+            }
+        }
+    }
+}
 
-            scat.push(scat_calc(
-                0,
-                xsdata,
-                meshid,
-                &flux,
-                neutron_energy,
-                energygroups,
-            ));
+// Gauss-Seidel pass over the fast groups, which only see downscattering, followed by the
+// thermal block iterated until the largest relative change of its flux is below the upscatter
+// tolerance. Returns the inner iterations and rates of every group along with the number of
+// thermal iterations.
+#[allow(clippy::too_many_arguments)]
+pub fn thermal_sweep(
+    flux: &mut [Vec<f32>],
+    fission: &[Vec<f32>],
+    inner: &InnerSolver,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+    thermal: usize,
+) -> (Vec<usize>, Vec<f32>, usize) {
+    let energygroups = variables.energygroups;
+    let groups = energygroups as usize;
+    let mut inner_iterations: Vec<usize> = vec![0; groups];
+    let mut inner_rates: Vec<f32> = vec![0.0; groups];
+    let mut sweep = |flux: &mut [Vec<f32>], neutron_energy: usize| {
+        (
+            inner_iterations[neutron_energy],
+            inner_rates[neutron_energy],
+        ) = solve_group(
+            flux,
+            fission,
+            inner,
+            xsdata,
+            meshid,
+            energygroups,
+            neutron_energy,
+            false,
+        );
+    };
 
-            for index in 1..n - 1 {
-                scat.push(scat_calc(
-                    index,
-                    xsdata,
-                    meshid,
-                    &flux,
-                    neutron_energy,
-                    energygroups,
-                ));
-            }
-            scat.push(scat_calc(
-                n - 1,
+    for neutron_energy in 0..thermal {
+        sweep(flux, neutron_energy);
+    }
+
+    let mut upscatter_iterations = 0;
+    while thermal < groups && upscatter_iterations < settings.max_upscatter {
+        upscatter_iterations += 1;
+        let previous: Vec<Vec<f32>> = flux[thermal..].to_vec();
+        for neutron_energy in thermal..groups {
+            sweep(flux, neutron_energy);
+        }
+        if settings.rebalance {
+            thermal_rebalance(
+                flux,
+                fission,
+                &inner.a_matrix,
                 xsdata,
                 meshid,
-                &flux,
-                neutron_energy,
                 energygroups,
-            ));
+                thermal,
+            );
+        }
+        let change = flux[thermal..]
+            .iter()
+            .flatten()
+            .zip(previous.iter().flatten())
+            .map(|(x, y)| ((x - y) / x).abs())
+            .fold(0.0, f32::max);
+        if change < settings.upscatter_tolerance {
+            break;
+        }
+    }
+    (inner_iterations, inner_rates, upscatter_iterations)
+}
 
-            //Calculate Flux vector now
-            let temp_flux: Vec<f32> = q[neutron_energy]
-                .iter()
-                .zip(scat.iter())
-                .map(|(x, y)| (x * k.powi(-1)) + y)
-                .collect();
-            let temp: f32 = a_inv_matrix[neutron_energy][0]
-                .iter()
-                .zip(temp_flux.iter())
-                .map(|(x, y)| x * y)
-                .sum::<f32>();
-            delta_flux = (((flux[neutron_energy][0] - temp) / flux[neutron_energy][0]).abs())
-                .max(delta_flux);
-            flux[neutron_energy][0] = temp;
-
-            for index in 1..n - 1 {
-                let temp_flux: Vec<f32> = q[neutron_energy]
-                    .iter()
-                    .zip(scat.iter())
-                    .map(|(x, y)| (x * k.powi(-1)) + y)
-                    .collect();
-                let temp: f32 = a_inv_matrix[neutron_energy][index]
-                    .iter()
-                    .zip(temp_flux.iter())
-                    .map(|(x, y)| x * y)
-                    .sum::<f32>();
-                delta_flux = ((flux[neutron_energy][index] - temp) / flux[neutron_energy][0]).abs();
-                flux[neutron_energy][index] = temp;
-            }
+// Scales every group of q by factor
+fn scale(q: &[Vec<f32>], factor: f32) -> Vec<Vec<f32>> {
+    q.iter()
+        .map(|group| group.iter().map(|x| x * factor).collect())
+        .collect()
+}
 
-            let temp_flux: Vec<f32> = q[neutron_energy]
-                .iter()
-                .zip(scat.iter())
-                .map(|(x, y)| (x * k.powi(-1)) + y)
-                .collect();
-            let temp: f32 = a_inv_matrix[neutron_energy][n - 1]
-                .iter()
-                .zip(temp_flux.iter())
-                .map(|(x, y)| x * y)
-                .sum::<f32>();
-            delta_flux = (((flux[neutron_energy][n - 1] - temp) / flux[neutron_energy][0]).abs())
-                .max(delta_flux);
-            flux[neutron_energy][n - 1] = temp;
-        }
+// Relative difference of two fission source distributions, each normalised to its total
+pub fn source_residual(q_new: &[Vec<f32>], q_old: &[Vec<f32>]) -> f32 {
+    let new_total: f32 = q_new.iter().flatten().sum();
+    let old_total: f32 = q_old.iter().flatten().sum();
+    q_new
+        .iter()
+        .flatten()
+        .zip(q_old.iter().flatten())
+        .map(|(x, y)| (x / new_total - y / old_total).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
 
-        q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
-        let temp_k = k;
-        k = temp_k * (q.iter().flatten().sum::<f32>() / temp_q.iter().flatten().sum::<f32>());
-        delta_k = ((k - temp_k) / temp_k).abs();
+// Chebyshev extrapolation parameters of step p within a cycle, for dominance ratio sigma
+fn chebyshev_parameters(p: usize, sigma: f32) -> (f32, f32) {
+    if p == 1 {
+        return (2.0 / (2.0 - sigma), 0.0);
     }
+    let gamma = (2.0 / sigma - 1.0).acosh();
+    let alpha = 4.0 / sigma * ((p as f32 - 1.0) * gamma).cosh() / (p as f32 * gamma).cosh();
+    (alpha, (1.0 - sigma / 2.0) * alpha - 1.0)
+}
+
+// Aitken's delta squared extrapolation of three successive eigenvalue estimates
+fn aitken(k: &[f32]) -> Option<f32> {
+    let [k0, k1, k2] = k[k.len() - 3..] else {
+        return None;
+    };
+    let denominator = k2 - 2.0 * k1 + k0;
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+    let extrapolated = k2 - (k2 - k1).powi(2) / denominator;
+    // Only accept the extrapolation when the sequence is behaving geometrically
+    ((extrapolated - k2).abs() < 10.0 * (k2 - k1).abs()).then_some(extrapolated)
+}
 
+// Scales the flux to the assembly power
+pub fn power_normalize(
+    flux: Vec<Vec<f32>>,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> Vec<Vec<f32>> {
+    let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+    let n: usize = meshid.len();
     let nut_matrix = (0..energygroups)
-        .into_iter()
         .map(|energy| {
             (0..meshid.len())
-                .into_iter()
-                .map(|index| xsdata.nut[(meshid[index].matid + (mattypes * energy as u8)) as usize])
+                .map(|index| {
+                    xsdata.nut[meshid[index].matid as usize + mattypes as usize * energy as usize]
+                })
                 .collect::<Vec<f32>>()
         })
         .collect::<Vec<Vec<f32>>>();
     let sigf_matrix = (0..energygroups)
-        .into_iter()
         .map(|energy| {
             (0..meshid.len())
-                .into_iter()
                 .map(|index| {
-                    xsdata.sigf[(meshid[index].matid + (mattypes * energy as u8)) as usize]
+                    xsdata.sigf[meshid[index].matid as usize + mattypes as usize * energy as usize]
                 })
                 .collect::<Vec<f32>>()
         })
@@ -332,25 +850,766 @@ pub fn nalgebra_method(
                 .map(|(x, y)| x * y.delta_x)
                 .sum::<f32>());
 
-    flux = (0..energygroups as usize)
-        .into_iter()
+    (0..energygroups as usize)
         .map(|energy| {
             flux[energy]
                 .iter()
                 .map(|x| x * power_constant)
                 .collect::<Vec<f32>>()
         })
-        .collect::<Vec<Vec<f32>>>();
+        .collect::<Vec<Vec<f32>>>()
+}
+
+// Outcome of a finite difference power iteration
+pub struct PowerIteration {
+    pub k: f32,
+    pub flux: Vec<Vec<f32>>,
+    pub outers: usize,
+    pub converged: bool,
+    pub history: Vec<[f32; 4]>, // k, k change, flux and fission source residuals of every outer
+    sweeps: usize,
+    upscatter: usize,
+    thermal: usize,
+}
+
+// Power iteration started from k and flux with the inner solver, acceleration and CMFD of the
+// settings. The outers print their progress when verbose.
+pub fn power_iteration(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+    mut k: f32,
+    mut flux: Vec<Vec<f32>>,
+    verbose: bool,
+) -> PowerIteration {
+    let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+    let n: usize = meshid.len();
+    let mut q: Vec<Vec<f32>> = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+    let (mut delta_flux, mut delta_k): (f32, f32) = (1.0, 1.0);
+
+    let inner = InnerSolver::new(
+        group_operators(xsdata, meshid, variables),
+        settings,
+        verbose,
+    );
+    let cells = coarse_cells(meshid, &settings.cmfd, variables.numass);
+    let thermal = thermal_group(xsdata, energygroups, mattypes);
+    let mut total_upscatter: usize = 0;
+
+    let mut outer: usize = 0;
+    let mut total_sweeps: usize = 0;
+    let mut k_history: Vec<f32> = Vec::new();
+    let mut previous_flux: Vec<Vec<f32>> = flux.clone();
+    let mut residuals: Vec<f32> = Vec::new();
+    let (mut chebyshev_step, mut sigma): (usize, f32) = (0, 0.0);
+
+    let mut delta_source: f32 = 1.0;
+    let mut history: Vec<[f32; 4]> = Vec::new();
+
+    while (delta_flux >= settings.flux_tolerance
+        || delta_source >= settings.source_tolerance
+        || delta_k >= settings.k_tolerance)
+        && outer < settings.max_outers
+    {
+        let temp_q = q.clone();
+        let temp_flux = flux.clone();
+
+        let (inner_iterations, inner_rates) = match settings.acceleration {
+            Acceleration::Wielandt => {
+                // Solve (M - F / k_s) flux = (1 / k - 1 / k_s) F flux_old. The shifted fission
+                // term is iterated with the group sweeps, lagged by one sweep. Every sweep
+                // iterates the upscattering and rebalances like the unshifted outers.
+                let k_shift = k + settings.shift;
+                let fixed = scale(&q, 1.0 / k - 1.0 / k_shift);
+                let mut result = (Vec::new(), Vec::new());
+                for _ in 0..WIELANDT_SWEEPS {
+                    let fission: Vec<Vec<f32>> =
+                        q_gen(xsdata, energygroups, mattypes, &flux, meshid)
+                            .iter()
+                            .zip(fixed.iter())
+                            .map(|(current, fixed)| {
+                                current
+                                    .iter()
+                                    .zip(fixed)
+                                    .map(|(x, y)| x / k_shift + y)
+                                    .collect()
+                            })
+                            .collect();
+                    let sweep_flux = flux.clone();
+                    let (iterations, rates, upscatter) = thermal_sweep(
+                        &mut flux, &fission, &inner, xsdata, meshid, variables, settings, thermal,
+                    );
+                    result = (iterations, rates);
+                    total_sweeps += 1;
+                    total_upscatter += upscatter;
+                    let change = flux
+                        .iter()
+                        .flatten()
+                        .zip(sweep_flux.iter().flatten())
+                        .map(|(x, y)| ((x - y) / x).abs())
+                        .fold(0.0, f32::max);
+                    if change < settings.flux_tolerance {
+                        break;
+                    }
+                }
+                result
+            }
+            _ => {
+                total_sweeps += 1;
+                let (iterations, rates, upscatter) = thermal_sweep(
+                    &mut flux,
+                    &scale(&q, 1.0 / k),
+                    &inner,
+                    xsdata,
+                    meshid,
+                    variables,
+                    settings,
+                    thermal,
+                );
+                total_upscatter += upscatter;
+                (iterations, rates)
+            }
+        };
+
+        // Largest relative change of the flux over every mesh and group
+        delta_flux = flux
+            .iter()
+            .flatten()
+            .zip(temp_flux.iter().flatten())
+            .map(|(x, y)| ((y - x) / y).abs())
+            .fold(0.0, f32::max);
+
+        q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+        let temp_k = k;
+        k = match settings.acceleration {
+            Acceleration::Wielandt => {
+                // The source ratio is the eigenvalue of the shifted problem
+                let k_shift = temp_k + settings.shift;
+                let gamma = (1.0 / temp_k - 1.0 / k_shift)
+                    / (q.iter().flatten().sum::<f32>() / temp_q.iter().flatten().sum::<f32>());
+                1.0 / (gamma + 1.0 / k_shift)
+            }
+            _ => temp_k * (q.iter().flatten().sum::<f32>() / temp_q.iter().flatten().sum::<f32>()),
+        };
+        k_history.push(k);
+        delta_source = source_residual(&q, &temp_q);
+        residuals.push(delta_source);
+        outer += 1;
+
+        match settings.acceleration {
+            Acceleration::Chebyshev => {
+                // The dominance ratio is estimated from the unaccelerated iterations
+                if chebyshev_step == 0 && outer >= CHEBYSHEV_START {
+                    sigma = (residuals[outer - 1] / residuals[outer - 2]).min(0.99);
+                    chebyshev_step = 1;
+                    if verbose {
+                        println!("estimated dominance ratio {:.6}", sigma);
+                    }
+                }
+                if chebyshev_step > 0 {
+                    let (alpha, beta) = chebyshev_parameters(chebyshev_step, sigma);
+                    for neutron_energy in 0..energygroups as usize {
+                        for index in 0..n {
+                            flux[neutron_energy][index] = temp_flux[neutron_energy][index]
+                                + alpha
+                                    * (flux[neutron_energy][index]
+                                        - temp_flux[neutron_energy][index])
+                                + beta
+                                    * (temp_flux[neutron_energy][index]
+                                        - previous_flux[neutron_energy][index]);
+                        }
+                    }
+                    q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+                    chebyshev_step = chebyshev_step % CHEBYSHEV_CYCLE + 1;
+                }
+            }
+            Acceleration::Aitken if outer.is_multiple_of(3) => {
+                if let Some(extrapolated) = aitken(&k_history) {
+                    k = extrapolated;
+                }
+            }
+            _ => {}
+        }
+
+        // The coarse mesh solution sets the fission source shape across the coarse cells
+        if !cells.is_empty() && outer.is_multiple_of(settings.cmfd_interval) {
+            let (coarse_k, coarse_iterations) = cmfd_update(
+                &mut flux,
+                k,
+                &inner.a_matrix,
+                &cells,
+                xsdata,
+                meshid,
+                variables,
+            );
+            if verbose {
+                println!(
+                    "outer {}: CMFD k {:.6} after {} coarse iterations",
+                    outer, coarse_k, coarse_iterations
+                );
+            }
+            k = coarse_k;
+            q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+        }
+        previous_flux = temp_flux;
+        delta_k = ((k - temp_k) / temp_k).abs();
+        history.push([k, delta_k, delta_flux, delta_source]);
+
+        if verbose && inner.is_iterative() {
+            println!(
+                "outer {}: inner iterations per group {:?}, convergence rates {:.4?}",
+                outer, inner_iterations, inner_rates
+            );
+        }
+    }
+
+    PowerIteration {
+        k,
+        flux,
+        outers: outer,
+        converged: outer < settings.max_outers,
+        history,
+        sweeps: total_sweeps,
+        upscatter: total_upscatter,
+        thermal,
+    }
+}
+
+pub fn nalgebra_method(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> SolutionResults {
+    let energygroups = variables.energygroups;
+    let flux = vec![vec![1.0; meshid.len()]; energygroups as usize];
+    let solution = power_iteration(xsdata, meshid, variables, settings, 1.0, flux, true);
+    let outer = solution.outers;
+
+    match solution.converged {
+        false => {
+            let [_, delta_k, delta_flux, delta_source] =
+                solution.history.last().copied().unwrap_or_default();
+            println!(
+                "warning: not converged after {} outers, flux {:.3e}, fission source {:.3e}, k {:.3e}",
+                outer, delta_flux, delta_source, delta_k
+            );
+        }
+        true => match settings.acceleration {
+            Acceleration::Wielandt => {
+                println!(
+                    "converged in {} outers ({} group sweeps)",
+                    outer, solution.sweeps
+                )
+            }
+            _ => println!("converged in {} outers", outer),
+        },
+    }
+    if solution.upscatter > 0 {
+        println!(
+            "thermal groups {} to {}: {} upscatter iterations, {:.1} per outer",
+            solution.thermal + 1,
+            energygroups,
+            solution.upscatter,
+            solution.upscatter as f32 / outer as f32
+        );
+    }
+
+    let flux = power_normalize(solution.flux, xsdata, meshid, variables);
 
     let temp_flux = flux.clone();
 
-    println!("{:.10}", k);
+    println!("{:.10}", solution.k);
 
     SolutionResults {
         flux,
-        assembly_average: average_assembly(temp_flux, numass, energygroups),
+        assembly_average: average_assembly(temp_flux, variables.numass, energygroups),
         fission_source: Vec::new(),
-        k: vec![k],
+        k: vec![solution.k],
         k_fund: Vec::new(),
+        multiplication: Vec::new(),
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history: solution.history,
+        generation_source: Vec::new(),
+    }
+}
+
+// Power iteration on the adjoint problem. Every group operator is symmetric, so only the
+// scattering and fission terms are transposed. Returns the adjoint flux and its eigenvalue, which
+// should match the forward one.
+pub fn adjoint_method(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> (Vec<Vec<f32>>, f32) {
+    let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+    let n: usize = meshid.len();
+    let inner = InnerSolver::new(group_operators(xsdata, meshid, variables), settings, true);
+    let mut adjoint: Vec<Vec<f32>> = vec![vec![1.0; n]; energygroups as usize];
+    let mut q = q_gen_adjoint(xsdata, energygroups, mattypes, &adjoint, meshid);
+    let mut k: f32 = 1.0;
+
+    let mut outer: usize = 0;
+    loop {
+        let temp_q = q.clone();
+        let temp_adjoint = adjoint.clone();
+        group_sweep(
+            &mut adjoint,
+            &scale(&q, 1.0 / k),
+            &inner,
+            xsdata,
+            meshid,
+            energygroups,
+            true,
+        );
+        q = q_gen_adjoint(xsdata, energygroups, mattypes, &adjoint, meshid);
+        let temp_k = k;
+        k = temp_k * (q.iter().flatten().sum::<f32>() / temp_q.iter().flatten().sum::<f32>());
+        outer += 1;
+
+        let largest: f32 = adjoint.iter().flatten().fold(0.0, |a, b| a.max(b.abs()));
+        let delta_flux: f32 = adjoint
+            .iter()
+            .flatten()
+            .zip(temp_adjoint.iter().flatten())
+            .map(|(x, y)| (x - y).abs())
+            .fold(0.0, f32::max)
+            / largest;
+        if delta_flux < settings.flux_tolerance
+            && ((k - temp_k) / temp_k).abs() < settings.k_tolerance
+        {
+            break;
+        }
+        if outer >= settings.max_outers {
+            println!("warning: adjoint not converged in {} outers", outer);
+            break;
+        }
+    }
+    println!("adjoint k {:.10} after {} outers", k, outer);
+
+    // The adjoint is only defined up to a constant, it is scaled to a peak importance of 1
+    let largest: f32 = adjoint.iter().flatten().fold(0.0, |a, b| a.max(b.abs()));
+    for value in adjoint.iter_mut().flatten() {
+        *value /= largest;
+    }
+    (adjoint, k)
+}
+
+// Solves the fixed source problem M flux = F flux + S by source iteration. The ratio of
+// successive fission source changes tends to k, so the iteration only converges for a
+// subcritical system, otherwise an error is returned.
+pub fn fixed_source(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> Result<SolutionResults, Box<dyn Error>> {
+    let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+    let n: usize = meshid.len();
+    if xsdata.source.len() != mattypes as usize * energygroups as usize {
+        return Err(format!(
+            "Source needs {} entries, one per material and group, {} were given",
+            mattypes as usize * energygroups as usize,
+            xsdata.source.len()
+        )
+        .into());
+    }
+
+    let external: Vec<Vec<f32>> = (0..energygroups)
+        .map(|energy| {
+            meshid
+                .iter()
+                .map(|mesh| {
+                    xsdata.source[mesh.matid as usize + mattypes as usize * energy as usize]
+                        * mesh.delta_x
+                })
+                .collect()
+        })
+        .collect();
+    let external_total: f32 = external.iter().flatten().sum();
+    if external_total <= 0.0 {
+        return Err("the external source is zero everywhere".into());
+    }
+
+    let inner = InnerSolver::new(group_operators(xsdata, meshid, variables), settings, true);
+    let mut flux: Vec<Vec<f32>> = vec![vec![0.0; n]; energygroups as usize];
+    let mut fission: Vec<Vec<f32>> = vec![vec![0.0; n]; energygroups as usize];
+    let (mut ratio, mut previous_change): (f32, f32) = (0.0, 0.0);
+    let mut growing: usize = 0;
+    let mut iteration: usize = 0;
+
+    loop {
+        let source: Vec<Vec<f32>> = external
+            .iter()
+            .zip(fission.iter())
+            .map(|(s, f)| s.iter().zip(f).map(|(x, y)| x + y).collect())
+            .collect();
+        let temp_flux = flux.clone();
+        group_sweep(
+            &mut flux,
+            &source,
+            &inner,
+            xsdata,
+            meshid,
+            energygroups,
+            false,
+        );
+        iteration += 1;
+
+        if settings.multiply {
+            let new_fission = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+            let change: f32 = new_fission
+                .iter()
+                .flatten()
+                .zip(fission.iter().flatten())
+                .map(|(x, y)| (x - y).abs())
+                .sum();
+            if previous_change > 0.0 {
+                ratio = change / previous_change;
+            }
+            previous_change = change;
+            fission = new_fission;
+
+            // A fission source that keeps growing by at least the previous change is supercritical
+            growing = match ratio >= 1.0 {
+                true => growing + 1,
+                false => 0,
+            };
+            if growing >= SUPERCRITICAL_ITERATIONS {
+                return Err(format!(
+                    "the system is supercritical, the source iteration ratio is {:.6}",
+                    ratio
+                )
+                .into());
+            }
+        }
+
+        let largest: f32 = flux.iter().flatten().fold(0.0, |a, b| a.max(b.abs()));
+        let delta_flux: f32 = flux
+            .iter()
+            .flatten()
+            .zip(temp_flux.iter().flatten())
+            .map(|(x, y)| (x - y).abs())
+            .fold(0.0, f32::max)
+            / largest;
+        if delta_flux < settings.flux_tolerance {
+            break;
+        }
+        if iteration >= settings.max_outers {
+            return Err(format!(
+                "the source iteration did not converge in {} iterations, ratio {:.6}",
+                iteration, ratio
+            )
+            .into());
+        }
+    }
+
+    // Total neutrons born per external source neutron, and the source multiplication factor
+    let multiplication: f32 =
+        (external_total + fission.iter().flatten().sum::<f32>()) / external_total;
+    let k_source = 1.0 - 1.0 / multiplication;
+    println!(
+        "fixed source converged in {} iterations, multiplication {:.6}, k source {:.6}",
+        iteration, multiplication, k_source
+    );
+
+    let temp_flux = flux.clone();
+    Ok(SolutionResults {
+        flux,
+        assembly_average: average_assembly(temp_flux, variables.numass, energygroups),
+        fission_source: Vec::new(),
+        k: vec![k_source],
+        k_fund: Vec::new(),
+        multiplication: vec![multiplication],
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history: Vec::new(),
+        generation_source: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::discrete::{
+        fixed_source, group_operators, jacobi, jacobi_spectral_radius, nalgebra_method,
+        optimal_omega, sor, thermal_group, thermal_sweep, thomas, InnerSolver, TriDiagonal,
+    };
+    use crate::{
+        Acceleration, CoarseMesh, Mesh, Preconditioner, Reactivity, Solver, SolverSettings, Sweep,
+        Variables, XSData,
+    };
+    use float_cmp::ApproxEq;
+
+    pub fn direct_settings() -> SolverSettings {
+        SolverSettings {
+            solver: Solver::Gaussian,
+            inner_tolerance: 1e-6,
+            max_inner: 1000,
+            omega: 0.0,
+            preconditioner: Preconditioner::Ilu0,
+            restart: 30,
+            cycle: 1,
+            acceleration: Acceleration::None,
+            shift: 0.1,
+            cmfd: CoarseMesh::None,
+            cmfd_interval: 3,
+            multiply: true,
+            adjoint: false,
+            perturbations: Vec::new(),
+            modes: 1,
+            node: CoarseMesh::Pin,
+            sn: 8,
+            sweep: Sweep::DiamondDifference,
+            flux_tolerance: 1e-5,
+            source_tolerance: 1e-5,
+            k_tolerance: 1e-6,
+            max_outers: 1000,
+            upscatter_tolerance: 1e-5,
+            max_upscatter: 50,
+            rebalance: false,
+            time_step: 1e-3,
+            end_time: 1.0,
+            theta: 0.5,
+            ramps: Vec::new(),
+            reactivity: Reactivity::Step(0.0),
+            generation: 0.0,
+            trace: String::new(),
+            search: None,
+            collapse: Vec::new(),
+            current_weighted: false,
+            homogenize: false,
+            sph: CoarseMesh::None,
+            peaking: false,
+            balance: false,
+        }
+    }
+
+    // Library of the given number of material and group entries without higher scattering
+    // moments, tests set the cross sections they use over it with ..library(entries)
+    pub fn library(entries: usize) -> XSData {
+        XSData {
+            sigs2: vec![0.0; entries],
+            sigs3: vec![0.0; entries],
+            mu: vec![0.0; entries],
+            ..Default::default()
+        }
+    }
+
+    // Reflected slab of one assembly with pins of 10 meshes
+    pub fn slab_variables(mattypes: u8, energygroups: u8) -> Variables {
+        Variables {
+            analk: 0,
+            mattypes,
+            energygroups,
+            generations: 1,
+            histories: 1,
+            skip: 0,
+            numass: 1,
+            numrods: 1,
+            roddia: 1.0,
+            rodpitch: 1.0,
+            mpfr: 10,
+            mpwr: 10,
+            boundl: 1.0,
+            boundr: 1.0,
+        }
+    }
+
+    // Meshes of equal width from x = 0, matid gives the material of every mesh
+    pub fn slab_meshes(cells: usize, delta_x: f32, matid: impl Fn(usize) -> u8) -> Vec<Mesh> {
+        (0..cells)
+            .map(|i| Mesh {
+                matid: matid(i),
+                delta_x,
+                mesh_left: i as f32 * delta_x,
+                mesh_right: (i + 1) as f32 * delta_x,
+            })
+            .collect()
+    }
+
+    // One group infinite medium, a uniform slab with reflective boundaries
+    pub fn infinite_medium(nu_sigf: f32) -> (XSData, Vec<Mesh>, Variables) {
+        let xsdata = XSData {
+            sigt: vec![1.0],
+            sigs: vec![0.9],
+            siga: vec![0.1],
+            sigf: vec![nu_sigf],
+            nut: vec![1.0],
+            chit: vec![1.0],
+            scat_matrix: vec![0.9],
+            inv_sigtr: vec![1.0],
+            source: vec![1.0],
+            ..library(1)
+        };
+        let variables = Variables {
+            mpfr: 5,
+            mpwr: 5,
+            ..slab_variables(1, 1)
+        };
+        (xsdata, slab_meshes(10, 0.5, |_| 0), variables)
+    }
+
+    // Two group infinite medium with downscatter only,
+    //     k = nu sigf1 / sigr1 + sig12 nu sigf2 / (sigr1 siga2)
+    //       = 0.005 / 0.03 + 0.02 * 0.15 / (0.03 * 0.1) = 7 / 6
+    pub fn two_group_medium() -> (XSData, Vec<Mesh>, Variables) {
+        let xsdata = XSData {
+            sigt: vec![0.2, 1.0],
+            sigs: vec![0.19, 0.9],
+            siga: vec![0.01, 0.1],
+            sigf: vec![0.002, 0.06],
+            nut: vec![2.5, 2.5],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![0.17, 0.02, 0.0, 0.9],
+            inv_sigtr: vec![1.0 / 0.2, 1.0],
+            ..library(2)
+        };
+        let (_, meshid, mut variables) = infinite_medium(0.0);
+        variables.energygroups = 2;
+        (xsdata, meshid, variables)
+    }
+
+    #[test]
+    fn test_fixed_source() {
+        // k infinity is 0.5, so the flux is S / (removal - nu sigf) and the multiplication 2
+        let (xsdata, meshid, variables) = infinite_medium(0.05);
+        let results = fixed_source(&xsdata, &meshid, &variables, &direct_settings()).unwrap();
+        assert!(results.flux[0].iter().all(|x| x.approx_eq(20.0, (1e-3, 2))));
+        assert!(results.multiplication[0].approx_eq(2.0, (1e-4, 2)));
+        assert!(results.k[0].approx_eq(0.5, (1e-3, 2)));
+
+        // Without multiplication only the removal is left
+        let mut settings = direct_settings();
+        settings.multiply = false;
+        let results = fixed_source(&xsdata, &meshid, &variables, &settings).unwrap();
+        assert!(results.flux[0].iter().all(|x| x.approx_eq(10.0, (1e-3, 2))));
+
+        // k infinity of 2 has no fixed source solution
+        let (xsdata, meshid, variables) = infinite_medium(0.2);
+        assert!(fixed_source(&xsdata, &meshid, &variables, &direct_settings()).is_err());
+    }
+
+    #[test]
+    fn test_boundary_conditions() {
+        // Two meshes of 1 cm with d = D / delta_x = 1 / 3 have a flat flux, so
+        // k = nu sigf / (siga + 2 d c / (2 d + c)) with c = (1 - albedo) / (2 (1 + albedo)).
+        // A vacuum gives c = 1 / 2 and k = 14 / 27, an albedo of 0.5 gives c = 1 / 6 and k = 6 / 7.
+        let (xsdata, _, mut variables) = infinite_medium(0.2);
+        let meshid = slab_meshes(2, 1.0, |_| 0);
+        for (albedo, k) in [(0.0, 14.0 / 27.0), (0.5, 6.0 / 7.0), (1.0, 2.0)] {
+            (variables.boundl, variables.boundr) = (albedo, albedo);
+            let results = nalgebra_method(&xsdata, &meshid, &variables, &direct_settings());
+            assert!(results.k[0].approx_eq(k, (1e-5, 2)));
+        }
+    }
+
+    #[test]
+    fn test_thermal_rebalance() {
+        // Two group infinite medium with strong upscattering and a unit source in group 1.
+        // The flux solves [0.2 -0.3; -0.15 0.4] phi = [1 0].
+        let (xsdata, meshid, variables) = two_group_medium();
+        let xsdata = XSData {
+            sigt: vec![0.5, 1.0],
+            sigs: vec![0.45, 0.9],
+            siga: vec![0.05, 0.1],
+            sigf: vec![0.0; 2],
+            nut: vec![0.0; 2],
+            scat_matrix: vec![0.3, 0.15, 0.3, 0.6],
+            inv_sigtr: vec![2.0, 1.0],
+            ..xsdata
+        };
+        let fission = vec![vec![0.5; 10], vec![0.0; 10]];
+        assert_eq!(thermal_group(&xsdata, 2, 1), 0);
+
+        let mut settings = direct_settings();
+        let mut iterations = Vec::new();
+        for rebalance in [false, true] {
+            settings.rebalance = rebalance;
+            let inner = InnerSolver::new(
+                group_operators(&xsdata, &meshid, &variables),
+                &settings,
+                true,
+            );
+            let mut flux = vec![vec![1.0; 10]; 2];
+            let (_, _, upscatter) = thermal_sweep(
+                &mut flux, &fission, &inner, &xsdata, &meshid, &variables, &settings, 0,
+            );
+            assert!(flux[0].iter().all(|x| x.approx_eq(0.4 / 0.035, (1e-3, 2))));
+            assert!(flux[1].iter().all(|x| x.approx_eq(0.15 / 0.035, (1e-3, 2))));
+            iterations.push(upscatter);
+        }
+        assert!(iterations[1] < iterations[0]);
+
+        // The Wielandt outers iterate the upscattering as well, k = nu sigf M^-1 chi
+        let xsdata = XSData {
+            sigf: vec![0.01, 0.01],
+            nut: vec![1.0, 1.0],
+            ..xsdata
+        };
+        let k = (0.01 * 0.4 + 0.01 * 0.15) / 0.035;
+        for acceleration in [Acceleration::None, Acceleration::Wielandt] {
+            settings.acceleration = acceleration;
+            let results = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+            assert!(results.k[0].approx_eq(k, (1e-4, 2)));
+        }
+    }
+
+    #[test]
+    fn test_thomas() {
+        let a = TriDiagonal {
+            lower: vec![0.0, -1.0, -1.0],
+            diag: vec![4.0, 4.0, 4.0],
+            upper: vec![-1.0, -1.0, 0.0],
+            row_sum: vec![3.0, 2.0, 3.0],
+        };
+        let x = thomas(&a, &[2.0, 4.0, 10.0]);
+        assert!(x[0].approx_eq(1.0, (1e-6, 2)));
+        assert!(x[1].approx_eq(2.0, (1e-6, 2)));
+        assert!(x[2].approx_eq(3.0, (1e-6, 2)));
+    }
+
+    #[test]
+    fn test_jacobi() {
+        let a = TriDiagonal {
+            lower: vec![0.0, -1.0, -1.0],
+            diag: vec![4.0, 4.0, 4.0],
+            upper: vec![-1.0, -1.0, 0.0],
+            row_sum: vec![3.0, 2.0, 3.0],
+        };
+        let (x, iterations, _) = jacobi(&a, &[3.0, 2.0, 3.0], &[1.0, 1.0, 1.0], 1e-7, 100);
+        assert_eq!(iterations, 1);
+        for value in x {
+            assert!(value.approx_eq(1.0, (1e-6, 2)));
+        }
+
+        let (x, iterations, _) = jacobi(&a, &[2.0, 4.0, 10.0], &[1.0, 1.0, 1.0], 1e-7, 100);
+        assert!(iterations > 1 && iterations < 100);
+        assert!(x[0].approx_eq(1.0, (1e-5, 2)));
+        assert!(x[1].approx_eq(2.0, (1e-5, 2)));
+        assert!(x[2].approx_eq(3.0, (1e-5, 2)));
+    }
+
+    #[test]
+    fn test_sor() {
+        let a = TriDiagonal {
+            lower: vec![0.0, -1.0, -1.0],
+            diag: vec![4.0, 4.0, 4.0],
+            upper: vec![-1.0, -1.0, 0.0],
+            row_sum: vec![3.0, 2.0, 3.0],
+        };
+        // The Jacobi eigenvalues of this matrix are 0 and +/- sqrt(2)/4
+        let rho = jacobi_spectral_radius(&a, 100);
+        assert!(rho.approx_eq(2.0_f32.sqrt() / 4.0, (1e-5, 2)));
+
+        let omega = optimal_omega(rho);
+        let (x, sor_iterations, _) = sor(&a, &[2.0, 4.0, 10.0], &[1.0, 1.0, 1.0], omega, 1e-7, 100);
+        let (_, jacobi_iterations, _) = jacobi(&a, &[2.0, 4.0, 10.0], &[1.0, 1.0, 1.0], 1e-7, 100);
+        assert!(sor_iterations < jacobi_iterations);
+        assert!(x[0].approx_eq(1.0, (1e-5, 2)));
+        assert!(x[1].approx_eq(2.0, (1e-5, 2)));
+        assert!(x[2].approx_eq(3.0, (1e-5, 2)));
     }
 }
@@ -0,0 +1,280 @@
+use csv::Writer;
+use std::error::Error;
+use std::ops::Range;
+
+use crate::discrete::assembly_ranges;
+use crate::{Mesh, SolutionResults, Variables, XSData};
+
+// Group constants of one homogeneous material, the scattering matrix is [from][to]
+pub struct Homogeneous {
+    pub sigt: Vec<f64>,
+    pub siga: Vec<f64>,
+    pub sigf: Vec<f64>,
+    pub nu_sigf: Vec<f64>,
+    pub chi: Vec<f64>,
+    pub diffusion: Vec<f64>,
+    pub scatter: Vec<Vec<f64>>,
+}
+
+// Homogenized constants of one assembly, its average flux and the discontinuity factors of
+// the left and right faces in every group
+pub struct Assembly {
+    pub constants: Homogeneous,
+    pub flux: Vec<f64>,
+    pub adf: Vec<[f64; 2]>,
+}
+
+pub fn material(xsdata: &XSData, variables: &Variables, matid: usize) -> Homogeneous {
+    let (groups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    let field = |values: &[f32]| -> Vec<f64> {
+        (0..groups)
+            .map(|g| values[matid + mattypes * g] as f64)
+            .collect()
+    };
+    Homogeneous {
+        sigt: field(&xsdata.sigt),
+        siga: field(&xsdata.siga),
+        sigf: field(&xsdata.sigf),
+        nu_sigf: (0..groups)
+            .map(|g| (xsdata.nut[matid + mattypes * g] * xsdata.sigf[matid + mattypes * g]) as f64)
+            .collect(),
+        chi: field(&xsdata.chit),
+        diffusion: field(&xsdata.inv_sigtr).iter().map(|x| x / 3.0).collect(),
+        scatter: (0..groups)
+            .map(|from| {
+                (0..groups)
+                    .map(|to| xsdata.scat_matrix[groups.pow(2) * matid + groups * from + to] as f64)
+                    .collect()
+            })
+            .collect(),
+    }
+}
+
+// Flux volume weighted constants of the meshes in range, scattering weighted by the flux of
+// the group it leaves and chi by the fission source. Without a flux the weights are the
+// volumes.
+pub fn homogenize(
+    flux: &[Vec<f32>],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    range: Range<usize>,
+) -> Homogeneous {
+    let groups = variables.energygroups as usize;
+    let materials: Vec<Homogeneous> = (0..variables.mattypes as usize)
+        .map(|matid| material(xsdata, variables, matid))
+        .collect();
+    let weight = |g: usize, index: usize| -> f64 {
+        let phi = match flux.len() == groups && flux[g].len() == meshid.len() {
+            true => flux[g][index] as f64,
+            false => 1.0,
+        };
+        phi * meshid[index].delta_x as f64
+    };
+
+    let mut homogeneous = Homogeneous {
+        sigt: vec![0.0; groups],
+        siga: vec![0.0; groups],
+        sigf: vec![0.0; groups],
+        nu_sigf: vec![0.0; groups],
+        chi: vec![0.0; groups],
+        diffusion: vec![0.0; groups],
+        scatter: vec![vec![0.0; groups]; groups],
+    };
+    let mut total = vec![0.0; groups];
+    for index in range {
+        let local = &materials[meshid[index].matid as usize];
+        let fission: f64 = (0..groups)
+            .map(|g| local.nu_sigf[g] * weight(g, index))
+            .sum();
+        for (g, total) in total.iter_mut().enumerate() {
+            let w = weight(g, index);
+            *total += w;
+            homogeneous.sigt[g] += local.sigt[g] * w;
+            homogeneous.siga[g] += local.siga[g] * w;
+            homogeneous.sigf[g] += local.sigf[g] * w;
+            homogeneous.nu_sigf[g] += local.nu_sigf[g] * w;
+            homogeneous.diffusion[g] += local.diffusion[g] * w;
+            homogeneous.chi[g] += local.chi[g] * fission;
+            for to in 0..groups {
+                homogeneous.scatter[g][to] += local.scatter[g][to] * w;
+            }
+        }
+    }
+    let chi_total: f64 = homogeneous.chi.iter().sum();
+    for (g, total) in total.iter().enumerate() {
+        homogeneous.sigt[g] /= total;
+        homogeneous.siga[g] /= total;
+        homogeneous.sigf[g] /= total;
+        homogeneous.nu_sigf[g] /= total;
+        homogeneous.diffusion[g] /= total;
+        if chi_total > 0.0 {
+            homogeneous.chi[g] /= chi_total;
+        }
+        for scatter in homogeneous.scatter[g].iter_mut() {
+            *scatter /= total;
+        }
+    }
+    homogeneous
+}
+
+// Flux on the face to the left of mesh x. Inside the problem it follows from the continuity of
+// the finite difference current, at the outer boundaries it is taken as the flux of the edge
+// mesh, which is exact for the reflective boundaries of a lattice calculation.
+fn face_flux(
+    flux: &[f32],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    mattypes: usize,
+    g: usize,
+    x: usize,
+) -> f64 {
+    if x == 0 || x == meshid.len() {
+        return flux[x.min(meshid.len() - 1)] as f64;
+    }
+    let coupling = |index: usize| -> f64 {
+        (xsdata.inv_sigtr[meshid[index].matid as usize + mattypes * g]
+            / 3.0
+            / meshid[index].delta_x) as f64
+    };
+    let (left, right) = (coupling(x - 1), coupling(x));
+    (left * flux[x - 1] as f64 + right * flux[x] as f64) / (left + right)
+}
+
+// Flux volume weighted constants of every assembly, partitioned as in average_assembly, with
+// the discontinuity factors as the ratio of the heterogeneous face flux to the assembly
+// average flux
+pub fn assembly_homogenization(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> Vec<Assembly> {
+    let (groups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    let flux = &results.flux;
+    let assemblies: Vec<Assembly> = assembly_ranges(meshid.len(), variables.numass)
+        .into_iter()
+        .map(|range| {
+            let volume: f64 = range.clone().map(|x| meshid[x].delta_x as f64).sum();
+            let average: Vec<f64> = (0..groups)
+                .map(|g| {
+                    range
+                        .clone()
+                        .map(|x| (flux[g][x] * meshid[x].delta_x) as f64)
+                        .sum::<f64>()
+                        / volume
+                })
+                .collect();
+            let adf: Vec<[f64; 2]> = (0..groups)
+                .map(|g| {
+                    [range.start, range.end]
+                        .map(|x| face_flux(&flux[g], xsdata, meshid, mattypes, g, x) / average[g])
+                })
+                .collect();
+            Assembly {
+                constants: homogenize(flux, xsdata, meshid, variables, range),
+                flux: average,
+                adf,
+            }
+        })
+        .collect();
+
+    for (index, assembly) in assemblies.iter().enumerate() {
+        let adf: Vec<String> = assembly
+            .adf
+            .iter()
+            .map(|x| format!("{:.4}/{:.4}", x[0], x[1]))
+            .collect();
+        println!(
+            "assembly {}: ADF left/right by group {}",
+            index + 1,
+            adf.join(" ")
+        );
+    }
+    assemblies
+}
+
+pub fn write_homogenization(assemblies: &[Assembly]) -> Result<(), Box<dyn Error>> {
+    let groups = assemblies.first().map_or(0, |x| x.flux.len());
+    let mut wtr = Writer::from_path("./homogenized.csv")?;
+    let mut header: Vec<String> = [
+        "assembly",
+        "group",
+        "flux",
+        "sigt",
+        "siga",
+        "sigf",
+        "nu_sigf",
+        "chi",
+        "diffusion",
+        "adf_left",
+        "adf_right",
+    ]
+    .iter()
+    .map(|x| x.to_string())
+    .collect();
+    header.extend((1..=groups).map(|to| format!("scat_to_{}", to)));
+    wtr.write_record(&header)?;
+
+    for (index, assembly) in assemblies.iter().enumerate() {
+        let constants = &assembly.constants;
+        for g in 0..groups {
+            let mut record: Vec<String> = vec![(index + 1).to_string(), (g + 1).to_string()];
+            record.extend(
+                [
+                    assembly.flux[g],
+                    constants.sigt[g],
+                    constants.siga[g],
+                    constants.sigf[g],
+                    constants.nu_sigf[g],
+                    constants.chi[g],
+                    constants.diffusion[g],
+                    assembly.adf[g][0],
+                    assembly.adf[g][1],
+                ]
+                .iter()
+                .chain(constants.scatter[g].iter())
+                .map(|x| x.to_string()),
+            );
+            wtr.write_record(&record)?;
+        }
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::infinite_medium;
+    use crate::homogenize::assembly_homogenization;
+    use crate::SolutionResults;
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_assembly_adf() {
+        // A linear flux 1 + x over two assemblies of one material, 5 meshes of 0.5 cm each.
+        // The first assembly averages 2.25, its left face takes the edge mesh flux 1.25 and
+        // the interface the mean 3.5 of its neighbours.
+        let (xsdata, meshid, mut variables) = infinite_medium(0.05);
+        variables.numass = 2;
+        let flux = vec![meshid
+            .iter()
+            .map(|mesh| 1.0 + 0.5 * (mesh.mesh_left + mesh.mesh_right))
+            .collect()];
+        let results = SolutionResults {
+            flux,
+            k: vec![1.0],
+            ..Default::default()
+        };
+        let assemblies = assembly_homogenization(&results, &xsdata, &meshid, &variables);
+        assert_eq!(assemblies.len(), 2);
+        assert!(assemblies[0].flux[0].approx_eq(2.25, (1e-6, 2)));
+        assert!(assemblies[0].adf[0][0].approx_eq(1.25 / 2.25, (1e-6, 2)));
+        assert!(assemblies[0].adf[0][1].approx_eq(3.5 / 2.25, (1e-6, 2)));
+        assert!(assemblies[1].adf[0][0].approx_eq(3.5 / 4.75, (1e-6, 2)));
+        // One material homogenizes to itself
+        assert!(assemblies[1].constants.siga[0].approx_eq(0.1, (1e-6, 2)));
+        assert!(assemblies[1].constants.nu_sigf[0].approx_eq(0.05, (1e-6, 2)));
+    }
+}
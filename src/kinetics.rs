@@ -0,0 +1,397 @@
+use csv::Writer;
+use std::error::Error;
+
+use crate::discrete::{group_operators, group_sweep, scat_calc, InnerSolver, TriDiagonal};
+use crate::perturbation::{apply, perturbed_problem};
+use crate::{Mesh, Perturbation, Ramp, SolutionResults, SolverSettings, Variables, XSData};
+
+// Power, flux and time of every step of a transient, starting with the steady state at t = 0
+pub struct Transient {
+    pub time: Vec<f32>,
+    pub power: Vec<f32>,
+    pub flux: Vec<Vec<Vec<f32>>>,
+}
+
+// Cross sections at time, every ramp moves the meshes in its region to a copy of their
+// materials holding the changed cross sections
+fn cross_sections(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    ramps: &[Ramp],
+    time: f32,
+) -> (XSData, Vec<Mesh>, Variables) {
+    let mut problem = (xsdata.clone(), meshid.to_vec(), Variables { ..*variables });
+    for ramp in ramps {
+        let fraction = match ramp.end > ramp.start {
+            true => ((time - ramp.start) / (ramp.end - ramp.start)).clamp(0.0, 1.0),
+            false => (time >= ramp.start) as u8 as f32,
+        };
+        if fraction > 0.0 {
+            let perturbation = Perturbation {
+                field: ramp.perturbation.field,
+                group: ramp.perturbation.group,
+                left: ramp.perturbation.left,
+                right: ramp.perturbation.right,
+                delta: ramp.perturbation.delta * fraction,
+            };
+            problem = perturbed_problem(&perturbation, &problem.0, &problem.1, &problem.2);
+        }
+    }
+    problem
+}
+
+// Fission production of every mesh, nu sigf phi summed over the groups and divided by the
+// steady state k so the initial state is critical
+fn production(
+    flux: &[Vec<f32>],
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    k: f32,
+) -> Vec<f32> {
+    let mattypes = variables.mattypes as usize;
+    meshid
+        .iter()
+        .enumerate()
+        .map(|(index, mesh)| {
+            (0..variables.energygroups as usize)
+                .map(|g| {
+                    let xs = mesh.matid as usize + mattypes * g;
+                    xsdata.nut[xs] * xsdata.sigf[xs] * flux[g][index]
+                })
+                .sum::<f32>()
+                / k
+        })
+        .collect()
+}
+
+fn total(production: &[f32], meshid: &[Mesh]) -> f32 {
+    production
+        .iter()
+        .zip(meshid)
+        .map(|(x, mesh)| x * mesh.delta_x)
+        .sum()
+}
+
+// Adds the time derivative, delta_x / (v theta dt), to the diagonal of every group operator
+fn time_operators(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    factor: f32,
+) -> Vec<TriDiagonal> {
+    let mut a_matrix = group_operators(xsdata, meshid, variables);
+    for (g, a) in a_matrix.iter_mut().enumerate() {
+        for (index, mesh) in meshid.iter().enumerate() {
+            let derivative = mesh.delta_x * factor / xsdata.velocity[g];
+            a.diag[index] += derivative;
+            a.row_sum[index] += derivative;
+        }
+    }
+    a_matrix
+}
+
+// Theta method solution of the time dependent diffusion equation with delayed neutron
+// precursors, starting from the steady state of nalgebra_method. Within a step
+//     (phi' - phi) / (v dt) = theta R(phi', C') + (1 - theta) R(phi, C)
+//     (C' - C) / dt = theta (beta P' - lambda C') + (1 - theta) (beta P - lambda C)
+// where R is the net production of the diffusion equation. C' is eliminated, leaving a fixed
+// source problem for phi' that is iterated with group sweeps.
+pub fn kinetics_method(
+    steady: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> Result<Transient, Box<dyn Error>> {
+    if xsdata.beta.is_empty() || xsdata.beta.len() != xsdata.lambda.len() {
+        return Err("Beta and Lambda need one value for every precursor group".into());
+    }
+    if xsdata.velocity.len() != variables.energygroups as usize {
+        return Err("Velocity needs one value for every energy group".into());
+    }
+    let energygroups = variables.energygroups as usize;
+    // Every active ramp doubles the materials, and the library is indexed in u8 up to
+    // groups^2 * mattypes
+    let ramped_mattypes = (0..settings.ramps.len())
+        .fold(variables.mattypes as usize, |mattypes, _| {
+            mattypes.saturating_mul(2)
+        });
+    if ramped_mattypes > u8::MAX as usize || energygroups.pow(2) * ramped_mattypes > 256 {
+        return Err(format!(
+            "{} ramps take the {} materials to {}, more than the u8 material indices allow in {} groups",
+            settings.ramps.len(),
+            variables.mattypes,
+            ramped_mattypes,
+            energygroups
+        )
+        .into());
+    }
+    let n = meshid.len();
+    let (dt, theta) = (settings.time_step, settings.theta);
+    let steps = (settings.end_time / dt).round() as usize;
+    let k = steady.k[0];
+    let beta_total: f32 = xsdata.beta.iter().sum();
+
+    // Precursor update C' = a C + b (theta P' + (1 - theta) P)
+    let (a, b): (Vec<f32>, Vec<f32>) = xsdata
+        .beta
+        .iter()
+        .zip(xsdata.lambda.iter())
+        .map(|(beta, lambda)| {
+            let denominator = 1.0 + theta * lambda * dt;
+            (
+                (1.0 - (1.0 - theta) * lambda * dt) / denominator,
+                beta * dt / denominator,
+            )
+        })
+        .unzip();
+    // Part of the delayed source at the end of the step carried by P'
+    let delayed_coupling: f32 = theta
+        * xsdata
+            .lambda
+            .iter()
+            .zip(b.iter())
+            .map(|(lambda, b)| lambda * b)
+            .sum::<f32>();
+
+    let mut problem = cross_sections(xsdata, meshid, variables, &settings.ramps, 0.0);
+    let mut flux = steady.flux.clone();
+    let mut fission = production(&flux, &problem.0, &problem.1, &problem.2, k);
+    let mut precursors: Vec<Vec<f32>> = xsdata
+        .beta
+        .iter()
+        .zip(xsdata.lambda.iter())
+        .map(|(beta, lambda)| fission.iter().map(|p| beta * p / lambda).collect())
+        .collect();
+    let initial_power = total(&fission, meshid);
+
+    let mut transient = Transient {
+        time: vec![0.0],
+        power: vec![1.0],
+        flux: vec![flux.clone()],
+    };
+    let spectrum = |xsdata: &XSData, meshid: &[Mesh], mattypes: u8, g: usize| -> Vec<(f32, f32)> {
+        meshid
+            .iter()
+            .map(|mesh| {
+                let xs = (mesh.matid + mattypes * g as u8) as usize;
+                (xsdata.chit[xs], xsdata.chid[xs])
+            })
+            .collect()
+    };
+
+    for step in 1..=steps {
+        let time = step as f32 * dt;
+        let (xs, mesh, vars) = (&problem.0, &problem.1, &problem.2);
+
+        // Source known at the start of the step, the explicit part of the theta method and
+        // the precursors carried over
+        let a_matrix = group_operators(xs, mesh, vars);
+        let explicit: Vec<Vec<f32>> = (0..energygroups)
+            .map(|g| {
+                let loss = apply(&a_matrix[g], &flux[g]);
+                let chi = spectrum(xs, mesh, vars.mattypes, g);
+                (0..n)
+                    .map(|i| {
+                        let delayed: f32 = (0..xsdata.beta.len())
+                            .map(|family| xsdata.lambda[family] * precursors[family][i])
+                            .sum();
+                        let carried: f32 = (0..xsdata.beta.len())
+                            .map(|family| {
+                                xsdata.lambda[family]
+                                    * (a[family] * precursors[family][i]
+                                        + b[family] * (1.0 - theta) * fission[i])
+                            })
+                            .sum();
+                        let residual = -loss[i] as f32
+                            + scat_calc(i, xs, mesh, &flux, g, vars.energygroups)
+                            + (chi[i].0 * (1.0 - beta_total) * fission[i] + chi[i].1 * delayed)
+                                * mesh[i].delta_x;
+                        mesh[i].delta_x * flux[g][i] / (xsdata.velocity[g] * theta * dt)
+                            + (1.0 - theta) / theta * residual
+                            + chi[i].1 * carried * mesh[i].delta_x
+                    })
+                    .collect()
+            })
+            .collect();
+
+        problem = cross_sections(xsdata, meshid, variables, &settings.ramps, time);
+        let (xs, mesh, vars) = (&problem.0, &problem.1, &problem.2);
+        let inner = InnerSolver::new(
+            time_operators(xs, mesh, vars, 1.0 / (theta * dt)),
+            settings,
+            true,
+        );
+        let spectra: Vec<Vec<(f32, f32)>> = (0..energygroups)
+            .map(|g| spectrum(xs, mesh, vars.mattypes, g))
+            .collect();
+
+        // Fission source iteration within the step
+        let previous_fission = fission.clone();
+        let mut converged = false;
+        for _ in 0..settings.max_outers {
+            let temp_flux = flux.clone();
+            let production = production(&flux, xs, mesh, vars, k);
+            let source: Vec<Vec<f32>> = (0..energygroups)
+                .map(|g| {
+                    (0..n)
+                        .map(|i| {
+                            explicit[g][i]
+                                + (spectra[g][i].0 * (1.0 - beta_total)
+                                    + spectra[g][i].1 * delayed_coupling)
+                                    * production[i]
+                                    * mesh[i].delta_x
+                        })
+                        .collect()
+                })
+                .collect();
+            group_sweep(
+                &mut flux,
+                &source,
+                &inner,
+                xs,
+                mesh,
+                vars.energygroups,
+                false,
+            );
+            let change = flux
+                .iter()
+                .flatten()
+                .zip(temp_flux.iter().flatten())
+                .map(|(x, y)| ((x - y) / x).abs())
+                .fold(0.0, f32::max);
+            if change < settings.flux_tolerance {
+                converged = true;
+                break;
+            }
+        }
+        if !converged {
+            println!("warning: time step {} not converged", step);
+        }
+
+        fission = production(&flux, xs, mesh, vars, k);
+        for (family, precursor) in precursors.iter_mut().enumerate() {
+            for (i, c) in precursor.iter_mut().enumerate() {
+                *c = a[family] * *c
+                    + b[family] * (theta * fission[i] + (1.0 - theta) * previous_fission[i]);
+            }
+        }
+
+        transient.time.push(time);
+        transient
+            .power
+            .push(total(&fission, meshid) / initial_power);
+        transient.flux.push(flux.clone());
+    }
+
+    let (peak, peak_time) = transient.power.iter().zip(transient.time.iter()).fold(
+        (0.0_f32, 0.0_f32),
+        |(p, t), (x, y)| match *x > p {
+            true => (*x, *y),
+            false => (p, t),
+        },
+    );
+    println!(
+        "transient: {} steps, peak power {:.6} at {:.4} s, final power {:.6}",
+        steps, peak, peak_time, transient.power[steps]
+    );
+    Ok(transient)
+}
+
+// Writes the relative power to transient.csv and the flux of every step to transient_flux.csv,
+// one row per step holding the time and the flux of each group in turn
+pub fn write_transient(transient: &Transient) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path("./transient.csv")?;
+    wtr.write_record(["time", "power"])?;
+    for (time, power) in transient.time.iter().zip(transient.power.iter()) {
+        wtr.write_record([time.to_string(), power.to_string()])?;
+    }
+    wtr.flush()?;
+
+    let mut wtr_flux = Writer::from_path("./transient_flux.csv")?;
+    for (time, flux) in transient.time.iter().zip(transient.flux.iter()) {
+        wtr_flux.write_record(
+            std::iter::once(time.to_string()).chain(flux.iter().flatten().map(|x| x.to_string())),
+        )?;
+    }
+    wtr_flux.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::nalgebra_method;
+    use crate::discrete::tests::{direct_settings, infinite_medium};
+    use crate::kinetics::kinetics_method;
+    use crate::{Perturbation, PerturbedField, Ramp, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_infinite_medium_step() {
+        // Reflected one group slab with one precursor group, which is exactly point kinetics.
+        // A step in SigA is compared against the two exponentials of the analytic solution.
+        let (beta, lambda, v) = (0.0065_f32, 0.08_f32, 2.2e5_f32);
+        let (siga, nu_sigf, delta) = (0.1_f32, 0.1_f32, -0.0003_f32);
+        let (xsdata, meshid, variables) = infinite_medium(nu_sigf);
+        let xsdata = XSData {
+            chid: vec![1.0],
+            beta: vec![beta],
+            lambda: vec![lambda],
+            velocity: vec![v],
+            ..xsdata
+        };
+        let mut settings = direct_settings();
+        settings.time_step = 1e-3;
+        settings.end_time = 0.5;
+        settings.flux_tolerance = 1e-7;
+        settings.ramps = vec![Ramp {
+            perturbation: Perturbation {
+                field: PerturbedField::SigA,
+                group: 0,
+                left: 0.0,
+                right: 5.0,
+                delta,
+            },
+            start: 0.0,
+            end: 0.0,
+        }];
+
+        let steady = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+        let transient = kinetics_method(&steady, &xsdata, &meshid, &variables, &settings).unwrap();
+
+        // d/dt (phi, C) = M (phi, C) from phi = 1 and C = beta nu sigf / lambda
+        let m = [
+            [v * ((1.0 - beta) * nu_sigf - siga - delta), v * lambda],
+            [beta * nu_sigf, -lambda],
+        ];
+        let (trace, det) = (m[0][0] + m[1][1], m[0][0] * m[1][1] - m[0][1] * m[1][0]);
+        let root = (0.25 * trace * trace - det).sqrt();
+        let (w1, w2) = (0.5 * trace + root, 0.5 * trace - root);
+        let slope = m[0][0] + m[0][1] * beta * nu_sigf / lambda;
+        let c1 = (slope - w2) / (w1 - w2);
+        let exact = |t: f32| c1 * (w1 * t).exp() + (1.0 - c1) * (w2 * t).exp();
+
+        for step in [100, 500] {
+            let time = transient.time[step];
+            assert!(transient.power[step].approx_eq(exact(time), (5e-4 * exact(time), 2)));
+        }
+
+        // Nine ramps would take the one material to 512, past the u8 material indices
+        settings.ramps = (0..9)
+            .map(|_| Ramp {
+                perturbation: Perturbation {
+                    field: PerturbedField::SigA,
+                    group: 0,
+                    left: 0.0,
+                    right: 5.0,
+                    delta,
+                },
+                start: 0.0,
+                end: 0.0,
+            })
+            .collect();
+        assert!(kinetics_method(&steady, &xsdata, &meshid, &variables, &settings).is_err());
+    }
+}
@@ -2,23 +2,190 @@ use std::iter::repeat;
 // Use these for timing
 use std::time::SystemTime;
 
-use crate::discrete::nalgebra_method;
+use crate::analytic::analytic_k;
+use crate::balance::{neutron_balance, write_balance};
+use crate::collapse::{energy_collapse, write_library};
+use crate::discrete::{fixed_source, nalgebra_method};
+use crate::homogenize::{assembly_homogenization, write_homogenization};
+use crate::kinetics::{kinetics_method, write_transient};
 use crate::mc_code::monte_carlo;
+use crate::modes::eigenmodes;
+use crate::nodal::nodal_method;
+use crate::perturbation::perturbation_analysis;
 use crate::plot_solution::plot_solution;
+use crate::point_kinetics::{
+    inverse_kinetics, point_kinetics, read_trace, write_inverse_kinetics, write_point_kinetics,
+    KineticsParameters,
+};
+use crate::power::{pin_powers, write_pin_powers};
 use crate::process_input::process_input;
-
+use crate::search::{critical_search, write_search};
+use crate::sp3::sp3_method;
+use crate::sph::{sph_method, write_sph};
+use crate::transport::transport_method;
+
+mod analytic;
+mod balance;
+mod cmfd;
+mod collapse;
 mod discrete;
+mod homogenize;
+mod kinetics;
 mod mc_code;
+mod modes;
+mod multigrid;
+mod nodal;
+mod perturbation;
 mod plot_solution;
+mod point_kinetics;
+mod power;
 mod process_input;
+mod search;
+mod sp3;
+mod sparse;
+mod sph;
+mod transport;
 
 pub enum Solver {
     LinAlg,
     Gaussian,
     Jacobian,
     SR,
+    ConjugateGradient,
+    BiCGStab,
+    Gmres,
+    Multigrid,
+}
+
+pub enum Acceleration {
+    None,
+    Wielandt,
+    Chebyshev,
+    Aitken,
+}
+
+pub enum Method {
+    Diffusion,
+    MonteCarlo,
+    FixedSource,
+    Nodal,
+    Transport,
+    SP3,
+    Kinetics,
+    PointKinetics,
+    InverseKinetics,
+}
+
+#[derive(Clone, Copy)]
+pub enum PerturbedField {
+    SigA,
+    SigF,
+    Density,
+}
+
+// A cross section change over the meshes whose centres lie in [left, right]. delta is an
+// absolute change for SigA and SigF and a relative change for Density.
+struct Perturbation {
+    field: PerturbedField,
+    group: usize, // 1 based, 0 perturbs every group
+    left: f32,
+    right: f32,
+    delta: f32,
+}
+
+// A cross section change ramped in linearly from start to end and held afterwards, a step
+// change has end equal to start
+struct Ramp {
+    perturbation: Perturbation,
+    start: f32,
+    end: f32,
+}
+
+// Reactivity of the point kinetics as a function of time
+pub enum Reactivity {
+    Step(f32),              // rho from t = 0
+    Ramp(f32),              // rho per s
+    Sine(f32, f32),         // amplitude and frequency in Hz
+    Table(Vec<(f32, f32)>), // time and rho, linear in between
+}
+
+// Input parameter adjusted by the criticality search
+pub enum SearchParameter {
+    SigA(u8), // absorption multiplier of one material
+    RodPitch, // fuel rod pitch in cm
+    Rods(u8), // number of water cells from the left replaced by this control rod material
+    Buckling, // transverse buckling in 1/cm^2, added as a D B^2 absorption
+}
+
+// Search for the parameter value in [low, high] that gives k = target
+struct Search {
+    parameter: SearchParameter,
+    low: f32,
+    high: f32,
+    target: f32,
+}
+
+pub enum CoarseMesh {
+    None,
+    Pin,
+    Assembly,
+}
+
+pub enum Sweep {
+    DiamondDifference,
+    StepCharacteristic,
+}
+
+pub enum Preconditioner {
+    Identity,
+    Jacobi,
+    Ilu0,
+}
+
+struct SolverSettings {
+    solver: Solver,
+    inner_tolerance: f32,           // convergence of the iterative inner solves
+    max_inner: usize,               // iteration limit of the iterative inner solves
+    omega: f32,                     // SOR relaxation factor, estimated per group when not positive
+    preconditioner: Preconditioner, // used by the Krylov solvers
+    restart: usize,                 // GMRES restart length
+    cycle: usize,                   // multigrid cycle, 1 for V cycles and 2 for W cycles
+    acceleration: Acceleration,     // acceleration of the outer (power) iteration
+    shift: f32,                     // Wielandt shift, k_s = k + shift
+    cmfd: CoarseMesh,               // coarse cells of the CMFD acceleration
+    cmfd_interval: usize,           // outers between CMFD updates
+    multiply: bool,                 // fission multiplication of the external source
+    adjoint: bool,                  // solve the adjoint problem after the eigenvalue
+    perturbations: Vec<Perturbation>, // first order reactivity of each, needs the adjoint
+    modes: usize,                   // number of eigenvalues and modes from the Arnoldi iteration
+    node: CoarseMesh,               // node size of the nodal expansion method
+    sn: usize,                      // number of S_N directions
+    sweep: Sweep,                   // spatial differencing of the S_N sweeps
+    flux_tolerance: f32,            // largest relative flux change of a converged outer
+    source_tolerance: f32,          // relative change of the normalised fission source
+    k_tolerance: f32,               // relative change of k
+    max_outers: usize,              // outer iteration limit
+    upscatter_tolerance: f32,       // convergence of the thermal upscatter iterations
+    max_upscatter: usize,           // thermal iteration limit within one outer
+    rebalance: bool,                // whole problem rebalance of the thermal groups
+    time_step: f32,                 // transient time step in s
+    end_time: f32,                  // transient end time in s
+    theta: f32,       // theta method weight, 1 is implicit Euler and 0.5 Crank-Nicolson
+    ramps: Vec<Ramp>, // cross section changes of the transient
+    reactivity: Reactivity, // point kinetics reactivity insertion
+    generation: f32,  // prompt generation time in s, from the diffusion solution if 0
+    trace: String,    // CSV of measured time and power for the inverse kinetics
+    search: Option<Search>, // criticality search run in place of the single solution
+    collapse: Vec<usize>, // coarse group of every group for the energy collapse, 1 based
+    current_weighted: bool, // current instead of flux weighted transport cross sections
+    homogenize: bool, // assembly cross sections and discontinuity factors
+    sph: CoarseMesh,  // cells of the SPH equivalence, None to skip it
+    peaking: bool,    // pin and assembly powers with the peaking factors
+    balance: bool,    // neutron balance tables of the diffusion solution
 }
 
+#[allow(dead_code)] // numrods is read in but not used by any solver yet
+#[derive(Clone)]
 struct Variables {
     analk: u8,          // 1 byte
     mattypes: u8,       // 1 byte
@@ -43,28 +210,27 @@ struct DeltaX {
     water: f32,
 }
 
+#[derive(Clone, Default)]
 struct XSData {
     sigt: Vec<f32>,
     sigs: Vec<f32>,
+    sigs2: Vec<f32>, // P2 moment of the within group scattering, used by SP3
+    sigs3: Vec<f32>, // P3 moment of the within group scattering, used by SP3
     mu: Vec<f32>,
     siga: Vec<f32>,
     sigf: Vec<f32>,
     nut: Vec<f32>,
     chit: Vec<f32>,
+    chid: Vec<f32>,     // delayed neutron emission spectrum
+    beta: Vec<f32>,     // delayed neutron fraction of every precursor group
+    lambda: Vec<f32>,   // decay constant of every precursor group in 1/s
+    velocity: Vec<f32>, // neutron speed of every energy group in cm/s
     scat_matrix: Vec<f32>,
     inv_sigtr: Vec<f32>,
+    source: Vec<f32>, // external source of the fixed source mode
 }
 
-struct CollapsedXsdata {
-    sigt: f32,
-    sigs: f32,
-    siga: f32,
-    sigf: f32,
-    mu: f32,
-    nu: f32,
-    chi: f32,
-}
-
+#[derive(Clone)]
 struct Mesh {
     matid: u8,      // 1 byte
     delta_x: f32,   // 4 bytes
@@ -74,12 +240,30 @@ struct Mesh {
                     // 3 bytes wasted
 }
 
+#[derive(Default)]
 struct SolutionResults {
     flux: Vec<Vec<f32>>,
     assembly_average: Vec<Vec<f32>>,
     fission_source: Vec<f32>,
     k: Vec<f32>,
     k_fund: Vec<f32>,
+    multiplication: Vec<f32>,
+    modes: Vec<Vec<Vec<f32>>>, // flux shape of every mode, [mode][group][mesh]
+    eigenvalues: Vec<f32>,     // k of every mode
+    history: Vec<[f32; 4]>,    // k, k change, flux and fission source residuals of every outer
+    generation_source: Vec<Vec<f32>>, // fission source of every active Monte Carlo generation
+}
+
+impl SolutionResults {
+    // Converged k, the running average over the active generations of a Monte Carlo solution
+    // and the eigenvalue of a deterministic one
+    fn k_eff(&self) -> f32 {
+        self.k_fund
+            .last()
+            .or(self.k.first())
+            .copied()
+            .unwrap_or(f32::NAN)
+    }
 }
 
 fn mesh_gen(matid: Vec<u8>, variables: &Variables, deltax: &DeltaX) -> (Vec<Mesh>, Vec<usize>) {
@@ -142,243 +326,217 @@ fn mesh_gen(matid: Vec<u8>, variables: &Variables, deltax: &DeltaX) -> (Vec<Mesh
     (mesh, fuel_indices)
 }
 
-// fn energy_collapse(
-//     meshid: &Vec<Mesh>,
-//     mattypes: u8,
-//     energygroups: u8,
-//     numrods: u8,
-//     mpfr: usize,
-//     flux: Vec<Vec<f32>>,
-//     xsdata: &XSData,
-// ) -> () {
-//     let mut xs_02: Vec<CollapsedXsdata> = Vec::with_capacity(meshid.len());
-//     let mut xs_24: Vec<CollapsedXsdata> = Vec::with_capacity(meshid.len());
-//     let mut xs_04: Vec<CollapsedXsdata> = Vec::with_capacity(meshid.len());
-
-//     for index in 0..meshid.len() {
-//         let (
-//             mut sigf_02,
-//             mut sigf_24,
-//             mut sigf_04,
-//             mut siga_02,
-//             mut siga_24,
-//             mut siga_04,
-//             mut sigs_02,
-//             mut sigs_24,
-//             mut sigs_04,
-//             mut mu_02,
-//             mut mu_24,
-//             mut mu_04,
-//             mut nu_02,
-//             mut nu_24,
-//             mut nu_04,
-//             mut chi_02,
-//             mut chi_24,
-//             mut chi_04,
-//         ) = (
-//             0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
-//             0.0,
-//         );
-
-//         for energy in 0..energygroups / 2 {
-//             sigf_02 += xsdata.sigf[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             sigf_24 += xsdata.sigf[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//             siga_02 += xsdata.siga[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             siga_24 += xsdata.siga[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//             sigs_02 += xsdata.sigs[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             sigs_24 += xsdata.sigs[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//             mu_02 += xsdata.mu[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             mu_24 += xsdata.mu[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//             nu_02 += xsdata.nut[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             nu_24 += xsdata.nut[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//             chi_02 += xsdata.chit[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             chi_24 += xsdata.chit[(meshid[index].matid + (mattypes * (energy + 2))) as usize]
-//                 * flux[energy as usize][index];
-//         }
-
-//         for energy in 0..energygroups {
-//             sigf_04 += xsdata.sigf[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             siga_04 += xsdata.siga[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             sigs_04 += xsdata.sigs[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             mu_04 += xsdata.mu[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             nu_04 += xsdata.nut[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//             chi_04 += xsdata.chit[(meshid[index].matid + (mattypes * energy)) as usize]
-//                 * flux[energy as usize][index];
-//         }
-
-//         let flux_cell_02: f32 = (0..energygroups / 2)
-//             .into_iter()
-//             .map(|energy| flux[energy as usize][index])
-//             .sum();
-//         let flux_cell_24: f32 = (energygroups / 2..energygroups)
-//             .into_iter()
-//             .map(|energy| flux[energy as usize][index])
-//             .sum();
-//         let flux_cell_04: f32 = flux_cell_02 + flux_cell_24;
-
-//         sigf_02 = sigf_02 / flux_cell_02;
-//         siga_02 = siga_02 / flux_cell_02;
-//         sigs_02 = sigs_02 / flux_cell_02;
-//         mu_02 = mu_02 / flux_cell_02;
-//         nu_02 = nu_02 / flux_cell_02;
-//         chi_02 = chi_02 / flux_cell_02;
-
-//         sigf_24 = sigf_24 / flux_cell_24;
-//         siga_24 = siga_24 / flux_cell_24;
-//         sigs_24 = sigs_24 / flux_cell_24;
-//         mu_24 = mu_24 / flux_cell_24;
-//         nu_24 = nu_24 / flux_cell_24;
-//         chi_24 = chi_24 / flux_cell_24;
-
-//         sigf_04 = sigf_04 / flux_cell_04;
-//         siga_04 = siga_04 / flux_cell_04;
-//         sigs_04 = sigs_04 / flux_cell_04;
-//         mu_04 = mu_04 / flux_cell_04;
-//         nu_04 = nu_04 / flux_cell_04;
-//         chi_04 = chi_04 / flux_cell_04;
-
-//         xs_02.push(CollapsedXsdata {
-//             sigt: sigf_02 + siga_02 + sigs_02,
-//             sigs: sigs_02,
-//             siga: siga_02,
-//             sigf: sigf_02,
-//             mu: mu_02,
-//             nu: nu_02,
-//             chi: chi_02,
-//         });
-
-//         xs_24.push(CollapsedXsdata {
-//             sigt: sigf_24 + siga_24 + sigs_24,
-//             sigs: sigs_24,
-//             siga: siga_24,
-//             sigf: sigf_24,
-//             mu: mu_24,
-//             nu: nu_24,
-//             chi: chi_24,
-//         });
-
-//         xs_04.push(CollapsedXsdata {
-//             sigt: sigf_04 + siga_04 + sigs_04,
-//             sigs: sigs_04,
-//             siga: siga_04,
-//             sigf: sigf_04,
-//             mu: mu_04,
-//             nu: nu_04,
-//             chi: chi_04,
-//         })
-//     }
-
-//     let sigt_02 = xs_02.iter().map(|x| x.sigt).sum::<f32>() / meshid.len() as f32;
-//     let sigt_24 = xs_24.iter().map(|x| x.sigt).sum::<f32>() / meshid.len() as f32;
-//     let sigt_04 = xs_04.iter().map(|x| x.sigt).sum::<f32>() / meshid.len() as f32;
-
-//     let siga_02 = xs_02.iter().map(|x| x.siga).sum::<f32>() / meshid.len() as f32;
-//     let siga_24 = xs_24.iter().map(|x| x.siga).sum::<f32>() / meshid.len() as f32;
-//     let siga_04 = xs_04.iter().map(|x| x.siga).sum::<f32>() / meshid.len() as f32;
-
-//     let sigf_02 = xs_02.iter().map(|x| x.sigf).sum::<f32>() / meshid.len() as f32;
-//     let sigf_24 = xs_24.iter().map(|x| x.sigf).sum::<f32>() / meshid.len() as f32;
-//     let sigf_04 = xs_04.iter().map(|x| x.sigf).sum::<f32>() / meshid.len() as f32;
-
-//     let sigs_02 = xs_02.iter().map(|x| x.sigs).sum::<f32>() / meshid.len() as f32;
-//     let sigs_24 = xs_24.iter().map(|x| x.sigs).sum::<f32>() / meshid.len() as f32;
-//     let sigs_04 = xs_04.iter().map(|x| x.sigs).sum::<f32>() / meshid.len() as f32;
-
-//     let mu_02 = xs_02.iter().map(|x| x.mu).sum::<f32>() / meshid.len() as f32;
-//     let mu_24 = xs_24.iter().map(|x| x.mu).sum::<f32>() / meshid.len() as f32;
-//     let mu_04 = xs_04.iter().map(|x| x.mu).sum::<f32>() / meshid.len() as f32;
-
-//     let nu_02 = xs_02.iter().map(|x| x.nu).sum::<f32>() / meshid.len() as f32;
-//     let nu_24 = xs_24.iter().map(|x| x.nu).sum::<f32>() / meshid.len() as f32;
-//     let nu_04 = xs_04.iter().map(|x| x.nu).sum::<f32>() / meshid.len() as f32;
-
-//     let chi_02 = xs_02.iter().map(|x| x.chi).sum::<f32>() / meshid.len() as f32;
-//     let chi_24 = xs_24.iter().map(|x| x.chi).sum::<f32>() / meshid.len() as f32;
-//     let chi_04 = xs_04.iter().map(|x| x.chi).sum::<f32>() / meshid.len() as f32;
-
-//     println!(
-//         "2 group 1 xs\nsigt: {}\nsigf: {}\nsiga: {}\nsigs: {}\nmu: {}\nnu: {}\nchi: {}\n",
-//         sigt_02, sigf_02, siga_02, sigs_02, mu_02, nu_02, chi_02
-//     );
-//     println!(
-//         "2 group 2 xs\nsigt: {}\nsigf: {}\nsiga: {}\nsigs: {}\nmu: {}\nnu: {}\nchi: {}\n",
-//         sigt_24, sigf_24, siga_24, sigs_24, mu_24, nu_24, chi_24
-//     );
-//     println!(
-//         "1 group xs\nsigt: {}\nsigf: {}\nsiga: {}\nsigs: {}\nmu: {}\nnu: {}\nchi: {}\n",
-//         sigt_04, sigf_04, siga_04, sigs_04, mu_04, nu_04, chi_04
-//     );
-// }
-
 fn main() {
-    // let now = SystemTime::now();
-
-    // let (variables, xsdata, matid, deltax, solution, solver) = process_input();
-
-    // let (meshid, fuel_indices) = mesh_gen(matid, &variables, &deltax);
-
-    // let results = match (solution, solver) {
-    //     (1, _) => monte_carlo(&variables, &xsdata, &deltax, &meshid, &fuel_indices, 1.0),
-    //     (_, Solver::LinAlg) => nalgebra_method(
-    //         &xsdata,
-    //         &meshid,
-    //         variables.energygroups,
-    //         variables.mattypes,
-    //         variables.boundl,
-    //         variables.boundr,
-    //         variables.numass,
-    //     ),
-    //     (_, _) => SolutionResults {
-    //         flux: Vec::new(),
-    //         assembly_average: Vec::new(),
-    //         fission_source: Vec::new(),
-    //         k: Vec::new(),
-    //         k_fund: Vec::new(),
-    //     }, // not implemented
-    // };
-
-    // // energy_collapse(&meshid, variables.mattypes, variables.energygroups, variables.numrods, variables.mpfr, results.flux.clone(), &xsdata);
-
-    // let _ = plot_solution(
-    //     results,
-    //     variables.energygroups,
-    //     variables.generations,
-    //     meshid.len(),
-    //     meshid[meshid.len() - 1].mesh_right,
-    // );
-
-    // print!(
-    //     "Run was completed in {} milliseconds \n",
-    //     now.elapsed().unwrap().as_millis()
-    // );
-
-    // below is for timing
-    let mut now = SystemTime::now();
-
-    for zyn in 0..100000 {
-        if zyn % 10000 == 0 {
-            print!(
-                "Average time over those 10000 runs was {} seconds \n",
-                now.elapsed().unwrap().as_micros() / 10000
-            );
-            now = SystemTime::now();
+    let now = SystemTime::now();
+
+    let (variables, xsdata, matid, deltax, method, settings) = match process_input() {
+        Ok(input) => input,
+        Err(error) => {
+            eprintln!("Input rejected: {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let (meshid, fuel_indices) = mesh_gen(matid.clone(), &variables, &deltax);
+
+    // Point and inverse kinetics only need the kinetics parameters, the diffusion solution is
+    // used to compute them when the generation time is not given
+    if let Method::PointKinetics | Method::InverseKinetics = method {
+        let parameters = match settings.generation > 0.0 {
+            true => KineticsParameters::from_input(&xsdata, settings.generation),
+            false => {
+                let results = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+                KineticsParameters::from_diffusion(
+                    &results, &xsdata, &meshid, &variables, &settings,
+                )
+            }
+        };
+        let parameters = match parameters {
+            Ok(parameters) => parameters,
+            Err(error) => {
+                eprintln!("Point kinetics failed: {}", error);
+                std::process::exit(1);
+            }
+        };
+        match method {
+            Method::InverseKinetics => match read_trace(&settings.trace) {
+                Ok((time, power)) => {
+                    let reactivity = inverse_kinetics(&parameters, &time, &power);
+                    let _ = write_inverse_kinetics(&parameters, &time, &power, &reactivity);
+                }
+                Err(error) => {
+                    eprintln!("Inverse kinetics failed: {}", error);
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                let history = point_kinetics(
+                    &parameters,
+                    &settings.reactivity,
+                    settings.time_step,
+                    settings.end_time,
+                );
+                let _ = write_point_kinetics(&history);
+            }
         }
-        let _ = process_input();
+        println!(
+            "Run was completed in {} milliseconds",
+            now.elapsed().unwrap().as_millis()
+        );
+        return;
     }
+
+    if let Some(search) = &settings.search {
+        match critical_search(search, &method, &xsdata, &matid, &variables, &settings) {
+            Ok(results) => {
+                let _ = write_search(&results);
+            }
+            Err(error) => {
+                eprintln!("Criticality search failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+        println!(
+            "Run was completed in {} milliseconds",
+            now.elapsed().unwrap().as_millis()
+        );
+        return;
+    }
+
+    let mut results = match method {
+        Method::Diffusion | Method::Kinetics | Method::PointKinetics | Method::InverseKinetics => {
+            nalgebra_method(&xsdata, &meshid, &variables, &settings)
+        }
+        Method::MonteCarlo => {
+            monte_carlo(&variables, &xsdata, &deltax, &meshid, &fuel_indices, 1.0)
+        }
+        Method::Nodal => nodal_method(&xsdata, &meshid, &variables, &settings),
+        Method::Transport => transport_method(&xsdata, &meshid, &variables, &settings),
+        Method::SP3 => sp3_method(&xsdata, &meshid, &variables, &settings),
+        Method::FixedSource => match fixed_source(&xsdata, &meshid, &variables, &settings) {
+            Ok(results) => results,
+            Err(error) => {
+                eprintln!("Fixed source solve failed: {}", error);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    if let Method::Diffusion = method {
+        if settings.modes > 1 {
+            let (eigenvalues, modes) =
+                eigenmodes(&xsdata, &meshid, &variables, &settings, settings.modes);
+            for (mode, k) in eigenvalues.iter().enumerate() {
+                println!("mode {}: k {:.10}", mode, k);
+            }
+            if eigenvalues.len() > 1 {
+                println!("dominance ratio {:.6}", eigenvalues[1] / eigenvalues[0]);
+            }
+            results.eigenvalues = eigenvalues;
+            results.modes = modes;
+        }
+        if settings.adjoint || !settings.perturbations.is_empty() {
+            let _ = perturbation_analysis(&results, &xsdata, &meshid, &variables, &settings);
+        }
+    }
+
+    // The transient starts from the steady state solution
+    if let Method::Kinetics = method {
+        match kinetics_method(&results, &xsdata, &meshid, &variables, &settings) {
+            Ok(transient) => {
+                let _ = write_transient(&transient);
+            }
+            Err(error) => {
+                eprintln!("Transient failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if variables.analk == 1 {
+        analytic_k(&results, &xsdata, &meshid, &variables);
+    }
+
+    if settings.homogenize {
+        let assemblies = assembly_homogenization(&results, &xsdata, &meshid, &variables);
+        let _ = write_homogenization(&assemblies);
+    }
+
+    if !matches!(settings.sph, CoarseMesh::None) {
+        match sph_method(
+            &results,
+            &xsdata,
+            &meshid,
+            &variables,
+            &settings,
+            &settings.sph,
+        ) {
+            Ok(sph) => {
+                let _ = write_sph(&sph);
+            }
+            Err(error) => {
+                eprintln!("SPH failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if settings.peaking {
+        match pin_powers(&results, &xsdata, &meshid, &fuel_indices, &variables) {
+            Ok(power) => {
+                let _ = write_pin_powers(&power, &meshid);
+            }
+            Err(error) => {
+                eprintln!("Pin power edit failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if settings.balance {
+        match neutron_balance(&results, &xsdata, &meshid, &variables, &method, &settings) {
+            Ok(balance) => {
+                let _ = write_balance(&balance, &meshid);
+            }
+            Err(error) => {
+                eprintln!("Neutron balance failed: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if !settings.collapse.is_empty() {
+        match energy_collapse(
+            &results.flux,
+            &xsdata,
+            &meshid,
+            &variables,
+            &settings.collapse,
+            settings.current_weighted,
+        ) {
+            Ok(collapsed) => {
+                let groups = settings.collapse.iter().copied().max().unwrap_or(0);
+                let _ = write_library(
+                    &collapsed,
+                    variables.mattypes as usize,
+                    groups,
+                    "./collapsed_xs.txt",
+                );
+            }
+            Err(error) => eprintln!("Energy collapse failed: {}", error),
+        }
+    }
+
+    let _ = plot_solution(
+        results,
+        variables.energygroups,
+        variables.generations,
+        meshid.len(),
+        meshid[meshid.len() - 1].mesh_right.into(),
+    );
+
+    println!(
+        "Run was completed in {} milliseconds",
+        now.elapsed().unwrap().as_millis()
+    );
 }
@@ -4,13 +4,7 @@ use std::thread;
 use crate::{DeltaX, Mesh, SolutionResults, Variables, XSData};
 
 #[inline(always)]
-fn energy(
-    chi: f32,
-    index: usize,
-    variables: &Variables,
-    xsdata: &XSData,
-    meshid: &Vec<Mesh>,
-) -> u8 {
+fn energy(chi: f32, index: usize, variables: &Variables, xsdata: &XSData, meshid: &[Mesh]) -> u8 {
     // Set values for use in later step. Optimized away anyway
     let skip = meshid[index].matid as usize;
     let step = variables.mattypes as usize;
@@ -38,17 +32,17 @@ fn direction(mu: f32) -> f32 {
 
 // #[inline(always)]
 fn spawn_neutron(
-    fuel_indices: &Vec<usize>,
+    fuel_indices: &[usize],
     variables: &Variables,
     xsdata: &XSData,
-    meshid: &Vec<Mesh>,
+    meshid: &[Mesh],
 ) -> (usize, f32, f32, u8) {
     let index = fuel_indices[thread_rng().gen_range(0..fuel_indices.len())];
     (
         index,
         random::<f32>(),
         direction(random::<f32>()),
-        energy(random::<f32>(), index, &variables, &xsdata, &meshid),
+        energy(random::<f32>(), index, variables, xsdata, meshid),
     )
 }
 
@@ -84,7 +78,7 @@ fn scat_mat_calc(
     matid: u8,
     neutron_energy: u8,
     inv_sigs: f32,
-    scat_matrix: &Vec<f32>,
+    scat_matrix: &[f32],
 ) -> Vec<f32> {
     let base_idx: usize =
         ((energygroups.pow(2) * matid) + (energygroups * neutron_energy)) as usize;
@@ -93,7 +87,6 @@ fn scat_mat_calc(
     let mut cumulative: f32 = 0.0;
 
     let scat_mat = (0..energygroups as usize)
-        .into_iter()
         .map(|_energy| {
             cumulative += scat_matrix[base_idx + _energy];
             cumulative * inv_sigs
@@ -131,9 +124,10 @@ fn interaction(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn particle_travel(
     mut tally: Vec<Vec<f32>>,
-    meshid: &Vec<Mesh>,
+    meshid: &[Mesh],
     mut mesh_index: usize,
     mut neutron_energy: u8,
     mut mu: f32,
@@ -148,7 +142,7 @@ fn particle_travel(
     let mut delta_s: f32 = mu * -random::<f32>().ln() * xsdata.inv_sigtr[xs_index];
 
     let mut same_material = true;
-    while same_material == true {
+    while same_material {
         let end_x = start_x + delta_s;
         let mesh_end = if mu >= 0.0 {
             meshid[mesh_index].mesh_right
@@ -192,8 +186,8 @@ fn particle_travel(
             );
 
             let (particle_exists, _neutron_energy, _mu) =
-                interaction(random::<f32>(), scat_mat, &xsdata, xs_index, neutron_energy);
-            if particle_exists == false {
+                interaction(random::<f32>(), scat_mat, xsdata, xs_index, neutron_energy);
+            if !particle_exists {
                 return (
                     particle_exists,
                     tally,
@@ -214,8 +208,8 @@ fn particle_travel(
 
 fn particle_lifetime(
     xsdata: &XSData,
-    meshid: &Vec<Mesh>,
-    fuel_indices: &Vec<usize>,
+    meshid: &[Mesh],
+    fuel_indices: &[usize],
     variables: &Variables,
     delta_x: &DeltaX,
     start: usize,
@@ -226,11 +220,11 @@ fn particle_lifetime(
     for _y in start..=end {
         // spawn_sub_mesh is the partial distance through the mesh
         let (mut mesh_index, spawn_sub_mesh, mut mu, mut neutron_energy) =
-            spawn_neutron(fuel_indices, &variables, &xsdata, &meshid);
+            spawn_neutron(fuel_indices, variables, xsdata, meshid);
         let mut start_x: f32 = meshid[mesh_index].mesh_left + (spawn_sub_mesh * delta_x.fuel);
 
         let mut particle_exists: bool = true;
-        while particle_exists == true {
+        while particle_exists {
             (
                 particle_exists,
                 tally,
@@ -261,7 +255,6 @@ fn average_assembly(mut results: SolutionResults, numass: u8, energygroups: u8)
     for energy in 0..energygroups as usize {
         for assembly in 1..=numass as usize {
             let average_ass: f32 = (((assembly - 1) * mesh_assembly)..(assembly * mesh_assembly))
-                .into_iter()
                 .map(|x| results.flux[energy][x])
                 .sum::<f32>()
                 / mesh_assembly as f32;
@@ -277,8 +270,8 @@ pub fn monte_carlo(
     variables: &Variables,
     xsdata: &XSData,
     delta_x: &DeltaX,
-    meshid: &Vec<Mesh>,
-    fuel_indices: &Vec<usize>,
+    meshid: &[Mesh],
+    fuel_indices: &[usize],
     mut k_new: f32,
 ) -> SolutionResults {
     let mut results = SolutionResults {
@@ -287,6 +280,11 @@ pub fn monte_carlo(
         fission_source: vec![0.0; meshid.len()],
         k: vec![0.0; variables.generations],
         k_fund: vec![0.0; variables.generations],
+        multiplication: Vec::new(),
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history: Vec::new(),
+        generation_source: Vec::new(),
     };
 
     println!("running MC code");
@@ -298,8 +296,8 @@ pub fn monte_carlo(
         k_new = 0.0;
 
         // For Multithreading
-        // One thread for the OS to use
-        let threads: usize = thread::available_parallelism().unwrap().get() - 1;
+        // One thread for the OS to use, unless there is only one
+        let threads: usize = (thread::available_parallelism().unwrap().get() - 1).max(1);
         let threaded_histories = variables.histories / threads;
         let starting_points: Vec<usize> = (0..threads).map(|x| x * threaded_histories).collect();
         let mut ending_points: Vec<usize> =
@@ -338,12 +336,13 @@ pub fn monte_carlo(
             }
         });
         let fund: f32 = 1.0 / (variables.generations - (variables.skip - 1)) as f32;
+        let mut generation_source = vec![0.0; meshid.len()];
 
-        for energy in 0..variables.energygroups as usize {
-            for index in 0..tally[energy].len() {
-                let delta_x = meshid[index].delta_x;
-                let matid = meshid[index].matid;
-                let flux = tally[energy][index] / (k * variables.histories as f32 * delta_x);
+        for (energy, group_tally) in tally.iter().enumerate() {
+            for (index, (mesh, score)) in meshid.iter().zip(group_tally).enumerate() {
+                let delta_x = mesh.delta_x;
+                let matid = mesh.matid;
+                let flux = score / (k * variables.histories as f32 * delta_x);
                 let fission_source = xsdata.nut
                     [(matid + (variables.mattypes * energy as u8)) as usize]
                     * xsdata.sigf[(matid + (variables.mattypes * energy as u8)) as usize]
@@ -352,14 +351,18 @@ pub fn monte_carlo(
                 if x >= variables.skip {
                     let conversion: f32 = (3565e6 * k * 36.2)
                         / (200e6
-                            * 1.602176634e-19
-                            * xsdata.nut[(0 + (variables.mattypes * 1)) as usize]
+                            * 1.602_176_6e-19
+                            * xsdata.nut[variables.mattypes as usize]
                             * meshid[meshid.len() - 1].mesh_right);
                     results.flux[energy][index] += flux * conversion * fund;
                     results.fission_source[index] += fission_source * fund;
+                    generation_source[index] += fission_source;
                 }
             }
         }
+        if x >= variables.skip {
+            results.generation_source.push(generation_source);
+        }
         results.k[x] = k_new;
     }
 
@@ -369,7 +372,6 @@ pub fn monte_carlo(
 
     for generations in (variables.skip + 1)..variables.generations {
         results.k_fund[generations] = (variables.skip..=generations)
-            .into_iter()
             .map(|x| results.k[x])
             .sum::<f32>()
             / (generations - (variables.skip - 1)) as f32;
@@ -385,7 +387,7 @@ mod tests {
     use crate::mc_code::{cross_mesh, direction, energy, hit_boundary, interaction, scat_mat_calc};
     #[allow(unused)]
     use crate::{DeltaX, Mesh, SolutionResults, Variables, XSData};
-    use float_cmp::approx_eq;
+
     #[allow(unused)]
     use float_cmp::ApproxEq;
 
@@ -483,11 +485,17 @@ mod tests {
         let xsdata = XSData {
             sigt: vec![0.200, 0.200, 0.200, 0.1, 1.00, 1.20, 1.10, 1.1],
             sigs: vec![0.200, 0.200, 0.200, 0.0, 0.80, 0.80, 1.10, 0.1],
+            sigs2: vec![0.0; 8],
+            sigs3: vec![0.0; 8],
             mu: vec![0.000, 0.000, 0.000, 0.0, 0.00, 0.00, 0.00, 0.0],
             siga: vec![0.000, 0.000, 0.000, 0.1, 0.20, 0.40, 0.00, 1.0],
             sigf: vec![0.000, 0.000, 0.000, 0.0, 0.18, 0.30, 0.00, 0.0],
             nut: vec![0.000, 0.000, 0.000, 0.0, 1.40, 1.50, 0.00, 0.0],
             chit: vec![1.000, 1.000, 0.000, 0.0, 0.00, 0.00, 0.00, 0.0],
+            chid: Vec::new(),
+            beta: Vec::new(),
+            lambda: Vec::new(),
+            velocity: Vec::new(),
             // Index via [(mattype * energygroups) + ((energygroups * starting_energy) + final_energy)]
             scat_matrix: vec![
                 0.185, 0.015, 0.000, 0.800, 0.185, 0.015, 0.000, 0.800, 0.170, 0.030, 0.000, 1.100,
@@ -499,10 +507,11 @@ mod tests {
                 5.0,
                 10.0,
                 1.00,
-                0.83333333,
-                0.9090909090909,
-                0.9090909090909,
+                0.833_333_3,
+                0.909_090_94,
+                0.909_090_94,
             ],
+            source: Vec::new(),
         };
 
         let scat_mat_check: Vec<Vec<f32>> = vec![
@@ -0,0 +1,276 @@
+use nalgebra::{DMatrix, DVector};
+
+use crate::discrete::{group_operators, group_sweep, InnerSolver};
+use crate::{Mesh, SolverSettings, Variables, XSData};
+
+// Convergence of the Ritz pairs, relative residual of K x = k x
+const MODE_TOLERANCE: f64 = 1e-6;
+const MAX_RESTARTS: usize = 100;
+// Limit of the group sweeps converging the upscattering within one application of K
+const UPSCATTER_SWEEPS: usize = 200;
+
+// The fission operator K = nu sigf (A - S)^-1 chi acting on the production density of every
+// mesh. Its eigenvalues are the k of the fundamental and higher modes.
+struct FissionOperator<'a> {
+    inner: InnerSolver<'a>,
+    xsdata: &'a XSData,
+    meshid: &'a [Mesh],
+    energygroups: u8,
+    upscatter: bool,
+    nu_sigf: Vec<Vec<f32>>,
+    // Emission spectrum times the mesh width
+    chi: Vec<Vec<f32>>,
+}
+
+impl<'a> FissionOperator<'a> {
+    fn new(
+        xsdata: &'a XSData,
+        meshid: &'a [Mesh],
+        variables: &Variables,
+        settings: &'a SolverSettings,
+    ) -> Self {
+        let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+        let field = |value: &dyn Fn(&Mesh, usize) -> f32| -> Vec<Vec<f32>> {
+            (0..energygroups as usize)
+                .map(|g| meshid.iter().map(|mesh| value(mesh, g)).collect())
+                .collect()
+        };
+        let nu_sigf = field(&|mesh, g| {
+            let index = (mesh.matid + mattypes * g as u8) as usize;
+            xsdata.nut[index] * xsdata.sigf[index]
+        });
+        let chi = field(&|mesh, g| {
+            xsdata.chit[(mesh.matid + mattypes * g as u8) as usize] * mesh.delta_x
+        });
+
+        let groups = energygroups as usize;
+        let upscatter = (0..mattypes as usize).any(|matid| {
+            (0..groups).any(|from| {
+                (0..from)
+                    .any(|to| xsdata.scat_matrix[groups.pow(2) * matid + groups * from + to] > 0.0)
+            })
+        });
+
+        FissionOperator {
+            inner: InnerSolver::new(group_operators(xsdata, meshid, variables), settings, true),
+            xsdata,
+            meshid,
+            energygroups,
+            upscatter,
+            nu_sigf,
+            chi,
+        }
+    }
+
+    // Returns K s along with the flux (A - S)^-1 chi s
+    fn apply(&self, s: &[f64]) -> (Vec<f64>, Vec<Vec<f32>>) {
+        let n = self.meshid.len();
+        let fission: Vec<Vec<f32>> = self
+            .chi
+            .iter()
+            .map(|chi| chi.iter().zip(s).map(|(x, y)| x * *y as f32).collect())
+            .collect();
+        let mut flux: Vec<Vec<f32>> = vec![vec![0.0; n]; self.energygroups as usize];
+        for _ in 0..UPSCATTER_SWEEPS {
+            let temp_flux = flux.clone();
+            group_sweep(
+                &mut flux,
+                &fission,
+                &self.inner,
+                self.xsdata,
+                self.meshid,
+                self.energygroups,
+                false,
+            );
+            if !self.upscatter {
+                break;
+            }
+            let largest: f32 = flux.iter().flatten().fold(0.0, |a, b| a.max(b.abs()));
+            let change: f32 = flux
+                .iter()
+                .flatten()
+                .zip(temp_flux.iter().flatten())
+                .map(|(x, y)| (x - y).abs())
+                .fold(0.0, f32::max);
+            if change <= 1e-6 * largest {
+                break;
+            }
+        }
+
+        let production: Vec<f64> = (0..n)
+            .map(|index| {
+                (0..self.energygroups as usize)
+                    .map(|g| (self.nu_sigf[g][index] * flux[g][index]) as f64)
+                    .sum()
+            })
+            .collect();
+        (production, flux)
+    }
+}
+
+fn dot(x: &[f64], y: &[f64]) -> f64 {
+    x.iter().zip(y).map(|(a, b)| a * b).sum()
+}
+
+// Eigenvector of the Hessenberg matrix for the Ritz value k by shifted inverse iteration
+fn ritz_vector(h: &DMatrix<f64>, k: f64) -> DVector<f64> {
+    let m = h.nrows();
+    let shifted = h - DMatrix::<f64>::identity(m, m) * (k * (1.0 + 1e-10));
+    let lu = shifted.lu();
+    let mut y = DVector::<f64>::from_element(m, 1.0);
+    for _ in 0..3 {
+        if let Some(solution) = lu.solve(&y) {
+            y = solution.normalize();
+        }
+    }
+    y
+}
+
+// Restarted Arnoldi iteration on the fission operator. Returns the count largest eigenvalues
+// with their flux shapes, each scaled to a peak of 1.
+pub fn eigenmodes(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+    count: usize,
+) -> (Vec<f32>, Vec<Vec<Vec<f32>>>) {
+    let operator = FissionOperator::new(xsdata, meshid, variables, settings);
+    let n = meshid.len();
+    let krylov = (4 * count + 20).min(n);
+
+    // Start from the production density of a flat flux
+    let mut start: Vec<f64> = operator.apply(&vec![1.0; n]).0;
+    let mut eigenvalues: Vec<f64> = Vec::new();
+    let mut vectors: Vec<Vec<f64>> = Vec::new();
+
+    let mut restart = 0;
+    let mut complex = 0;
+    while restart < MAX_RESTARTS {
+        restart += 1;
+        let norm = dot(&start, &start).sqrt();
+        let mut basis: Vec<Vec<f64>> = vec![start.iter().map(|x| x / norm).collect()];
+        let mut h = DMatrix::<f64>::zeros(krylov + 1, krylov);
+
+        let mut size = krylov;
+        for j in 0..krylov {
+            let (mut w, _) = operator.apply(&basis[j]);
+            // Modified Gram-Schmidt, repeated once to keep the basis orthogonal
+            for _ in 0..2 {
+                for (i, v) in basis.iter().enumerate() {
+                    let projection = dot(&w, v);
+                    h[(i, j)] += projection;
+                    for (x, y) in w.iter_mut().zip(v) {
+                        *x -= projection * y;
+                    }
+                }
+            }
+            let norm = dot(&w, &w).sqrt();
+            h[(j + 1, j)] = norm;
+            if norm < 1e-12 * h[(0, 0)].abs() {
+                // The Krylov space is invariant
+                size = j + 1;
+                break;
+            }
+            basis.push(w.iter().map(|x| x / norm).collect());
+        }
+
+        let hessenberg = h.view((0, 0), (size, size)).into_owned();
+        let values = hessenberg.complex_eigenvalues();
+        let mut ritz: Vec<f64> = values
+            .iter()
+            .filter(|value| value.im.abs() <= 1e-8 * value.re.abs())
+            .map(|value| value.re)
+            .collect();
+        complex = values.len() - ritz.len();
+        ritz.sort_by(|a, b| b.abs().total_cmp(&a.abs()));
+        ritz.truncate(count);
+
+        let mut converged = true;
+        eigenvalues.clear();
+        vectors.clear();
+        for &k in ritz.iter() {
+            let y = ritz_vector(&hessenberg, k);
+            // Residual of the Ritz pair from the last row of the Arnoldi relation
+            let residual = match size < h.nrows() {
+                true => (h[(size, size - 1)] * y[size - 1]).abs(),
+                false => 0.0,
+            };
+            converged &= residual <= MODE_TOLERANCE * k.abs();
+            let vector: Vec<f64> = (0..n)
+                .map(|index| (0..size).map(|i| basis[i][index] * y[i]).sum())
+                .collect();
+            eigenvalues.push(k);
+            vectors.push(vector);
+        }
+
+        if converged || size < krylov {
+            break;
+        }
+        // Explicit restart from the sum of the wanted Ritz vectors
+        start = vectors.iter().fold(vec![0.0; n], |sum, v| {
+            sum.iter().zip(v).map(|(a, b)| a + b).collect()
+        });
+    }
+    println!(
+        "Arnoldi iteration: {} eigenvalues after {} restarts",
+        eigenvalues.len(),
+        restart
+    );
+    if eigenvalues.len() < count && complex > 0 {
+        // Complex Ritz pairs have no real mode to report
+        println!(
+            "warning: {} complex Ritz values discarded, {} of {} modes returned",
+            complex,
+            eigenvalues.len(),
+            count
+        );
+    }
+
+    let modes: Vec<Vec<Vec<f32>>> = vectors
+        .iter()
+        .map(|vector| {
+            let (_, mut flux) = operator.apply(vector);
+            // The largest entry is made positive so the fundamental mode is positive everywhere
+            let peak: f32 = flux
+                .iter()
+                .flatten()
+                .fold(0.0, |a: f32, b| if b.abs() > a.abs() { *b } else { a });
+            for value in flux.iter_mut().flatten() {
+                *value /= peak;
+            }
+            flux
+        })
+        .collect();
+
+    (eigenvalues.iter().map(|k| *k as f32).collect(), modes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::{direct_settings, infinite_medium, slab_meshes};
+    use crate::modes::eigenmodes;
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_reflected_slab_modes() {
+        // One group uniform slab with reflective boundaries, the modes are cosines and the
+        // discrete eigenvalues are nu sigf / (removal + 2 D (1 - cos(n pi / N)) / h^2)
+        let (cells, h) = (20, 0.5_f32);
+        let (xsdata, _, variables) = infinite_medium(0.12);
+        let meshid = slab_meshes(cells, h, |_| 0);
+
+        let (eigenvalues, modes) = eigenmodes(&xsdata, &meshid, &variables, &direct_settings(), 3);
+        assert_eq!(eigenvalues.len(), 3);
+        for (mode, k) in eigenvalues.iter().enumerate() {
+            let leakage = 2.0 / 3.0
+                * (1.0 - (mode as f32 * std::f32::consts::PI / cells as f32).cos())
+                / h.powi(2);
+            assert!(k.approx_eq(0.12 / (0.1 + leakage), (1e-5, 2)));
+        }
+
+        // The fundamental is flat and the first harmonic changes sign half way
+        assert!(modes[0][0].iter().all(|x| x.approx_eq(1.0, (1e-4, 2))));
+        assert!(modes[1][0][0] * modes[1][0][cells - 1] < 0.0);
+    }
+}
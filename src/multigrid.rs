@@ -0,0 +1,266 @@
+use crate::discrete::TriDiagonal;
+
+// One grid of the hierarchy. The operator is kept in double precision, the Galerkin products
+// would otherwise lose the removal term on fine meshes.
+struct Level {
+    lower: Vec<f64>,
+    diag: Vec<f64>,
+    upper: Vec<f64>,
+    // For every fine mesh, the coarse meshes it is interpolated from and their weights
+    prolongation: Vec<Vec<(usize, f64)>>,
+}
+
+impl Level {
+    fn len(&self) -> usize {
+        self.diag.len()
+    }
+
+    fn off_diagonal(&self, i: usize, x: &[f64]) -> f64 {
+        let mut sum = 0.0;
+        if i > 0 {
+            sum += self.lower[i] * x[i - 1];
+        }
+        if i + 1 < self.len() {
+            sum += self.upper[i] * x[i + 1];
+        }
+        sum
+    }
+
+    fn residual(&self, b: &[f64], x: &[f64]) -> Vec<f64> {
+        (0..self.len())
+            .map(|i| b[i] - self.diag[i] * x[i] - self.off_diagonal(i, x))
+            .collect()
+    }
+
+    // Symmetric Gauss-Seidel, a forward and a backward sweep
+    fn smooth(&self, b: &[f64], x: &mut [f64], sweeps: usize) {
+        for _ in 0..sweeps {
+            for i in 0..self.len() {
+                x[i] = (b[i] - self.off_diagonal(i, x)) / self.diag[i];
+            }
+            for i in (0..self.len()).rev() {
+                x[i] = (b[i] - self.off_diagonal(i, x)) / self.diag[i];
+            }
+        }
+    }
+
+    // Direct solve used on the coarsest grid
+    fn thomas(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.len();
+        let mut c_prime = vec![0.0; n];
+        let mut x = vec![0.0; n];
+        c_prime[0] = self.upper[0] / self.diag[0];
+        x[0] = b[0] / self.diag[0];
+        for i in 1..n {
+            let denominator = self.diag[i] - self.lower[i] * c_prime[i - 1];
+            c_prime[i] = self.upper[i] / denominator;
+            x[i] = (b[i] - self.lower[i] * x[i - 1]) / denominator;
+        }
+        for i in (0..n - 1).rev() {
+            x[i] -= c_prime[i] * x[i + 1];
+        }
+        x
+    }
+
+    // Every other mesh is kept on the coarse grid. The meshes in between are interpolated with
+    // the operator's own coupling coefficients, which carry the harmonic mean diffusion
+    // coefficients across material interfaces, so the coarse correction keeps the flux
+    // kinks at the interfaces. The coarse operator is the Galerkin product R A P with R = P^T.
+    fn coarsen(&mut self) -> Level {
+        let n = self.len();
+        let n_coarse = n.div_ceil(2);
+
+        self.prolongation = (0..n)
+            .map(|i| {
+                if i % 2 == 0 {
+                    vec![(i / 2, 1.0)]
+                } else if i + 1 < n {
+                    vec![
+                        ((i - 1) / 2, -self.lower[i] / self.diag[i]),
+                        (i.div_ceil(2), -self.upper[i] / self.diag[i]),
+                    ]
+                } else {
+                    vec![((i - 1) / 2, -self.lower[i] / self.diag[i])]
+                }
+            })
+            .collect();
+
+        let mut coarse = Level {
+            lower: vec![0.0; n_coarse],
+            diag: vec![0.0; n_coarse],
+            upper: vec![0.0; n_coarse],
+            prolongation: Vec::new(),
+        };
+        for i in 0..n {
+            let stencil = [
+                (i.wrapping_sub(1), self.lower[i]),
+                (i, self.diag[i]),
+                (i + 1, self.upper[i]),
+            ];
+            for &(row, row_weight) in self.prolongation[i].iter() {
+                for &(j, value) in stencil.iter().filter(|(j, _)| *j < n) {
+                    for &(column, column_weight) in self.prolongation[j].iter() {
+                        let entry = row_weight * value * column_weight;
+                        match column as isize - row as isize {
+                            -1 => coarse.lower[row] += entry,
+                            0 => coarse.diag[row] += entry,
+                            1 => coarse.upper[row] += entry,
+                            _ => {
+                                unreachable!("the Galerkin product of a 1D stencil is tridiagonal")
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        coarse
+    }
+
+    fn restrict(&self, fine: &[f64], n_coarse: usize) -> Vec<f64> {
+        let mut coarse = vec![0.0; n_coarse];
+        for (i, weights) in self.prolongation.iter().enumerate() {
+            for &(index, weight) in weights.iter() {
+                coarse[index] += weight * fine[i];
+            }
+        }
+        coarse
+    }
+
+    fn prolong(&self, coarse: &[f64]) -> Vec<f64> {
+        self.prolongation
+            .iter()
+            .map(|weights| {
+                weights
+                    .iter()
+                    .map(|(index, weight)| weight * coarse[*index])
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+pub struct Multigrid {
+    levels: Vec<Level>,
+}
+
+impl Multigrid {
+    // Builds the grid hierarchy of a group operator, coarsening down to a few meshes
+    pub fn new(a: &TriDiagonal) -> Self {
+        let n = a.len();
+        // The diagonal is rebuilt from the row sums so the removal term survives in f64
+        let mut finest = Level {
+            lower: a.lower.iter().map(|x| *x as f64).collect(),
+            diag: (0..n)
+                .map(|i| a.row_sum[i] as f64 - a.lower[i] as f64 - a.upper[i] as f64)
+                .collect(),
+            upper: a.upper.iter().map(|x| *x as f64).collect(),
+            prolongation: Vec::new(),
+        };
+
+        let mut levels = Vec::new();
+        while finest.len() > 3 {
+            let coarse = finest.coarsen();
+            levels.push(finest);
+            finest = coarse;
+        }
+        levels.push(finest);
+        Multigrid { levels }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    // One multigrid cycle on level, gamma = 1 gives a V cycle and gamma = 2 a W cycle
+    fn cycle(&self, level: usize, b: &[f64], x: &mut [f64], gamma: usize) {
+        let grid = &self.levels[level];
+        if level + 1 == self.levels.len() {
+            x.copy_from_slice(&grid.thomas(b));
+            return;
+        }
+
+        grid.smooth(b, x, 2);
+        let n_coarse = self.levels[level + 1].len();
+        let coarse_b = grid.restrict(&grid.residual(b, x), n_coarse);
+        let mut coarse_x = vec![0.0; n_coarse];
+        for _ in 0..gamma {
+            self.cycle(level + 1, &coarse_b, &mut coarse_x, gamma);
+        }
+        for (x, correction) in x.iter_mut().zip(grid.prolong(&coarse_x)) {
+            *x += correction;
+        }
+        grid.smooth(b, x, 2);
+    }
+
+    // Cycles until the residual is reduced below tolerance relative to the source
+    pub fn solve(
+        &self,
+        b: &[f32],
+        guess: &[f32],
+        gamma: usize,
+        tolerance: f32,
+        max_iterations: usize,
+    ) -> (Vec<f32>, usize, f32) {
+        let finest = &self.levels[0];
+        let b: Vec<f64> = b.iter().map(|x| *x as f64).collect();
+        let mut x: Vec<f64> = guess.iter().map(|x| *x as f64).collect();
+        let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+
+        let tolerance = tolerance as f64 * norm(&b);
+        let initial = norm(&finest.residual(&b, &x));
+        let mut residual = initial;
+        let mut iterations = 0;
+        while residual > tolerance && iterations < max_iterations {
+            self.cycle(0, &b, &mut x, gamma);
+            residual = norm(&finest.residual(&b, &x));
+            iterations += 1;
+        }
+
+        let rate = if iterations > 0 && initial > 0.0 {
+            (residual / initial).powf(1.0 / iterations as f64) as f32
+        } else {
+            0.0
+        };
+        (x.iter().map(|x| *x as f32).collect(), iterations, rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::TriDiagonal;
+    use crate::multigrid::Multigrid;
+
+    // Diffusion operator on n meshes with a jump in the coupling coefficient half way
+    fn interface_operator(n: usize) -> TriDiagonal {
+        let coupling: Vec<f32> = (0..n - 1)
+            .map(|i| if i < n / 2 { 100.0 } else { 1.0 })
+            .collect();
+        let mut a = TriDiagonal::new(n);
+        for i in 0..n {
+            a.row_sum[i] = 0.01;
+            if i > 0 {
+                a.lower[i] = -coupling[i - 1];
+            }
+            if i + 1 < n {
+                a.upper[i] = -coupling[i];
+            }
+            a.diag[i] = a.row_sum[i] - a.lower[i] - a.upper[i];
+        }
+        a
+    }
+
+    #[test]
+    fn test_mesh_independent_convergence() {
+        let mut cycles = Vec::new();
+        for n in [64, 1024, 16384] {
+            let a = interface_operator(n);
+            let multigrid = Multigrid::new(&a);
+            assert!(multigrid.depth() > 1);
+            let (x, iterations, _) = multigrid.solve(&vec![1.0; n], &vec![0.0; n], 1, 1e-6, 50);
+            // With a uniform source and removal everywhere the solution is 1 / 0.01
+            assert!(x.iter().all(|value| (value - 100.0).abs() < 1e-2));
+            cycles.push(iterations);
+        }
+        assert!(cycles.iter().all(|iterations| *iterations <= cycles[0] + 1));
+    }
+}
@@ -0,0 +1,427 @@
+use crate::cmfd::coarse_cells;
+use crate::discrete::{average_assembly, power_iteration, power_normalize};
+use crate::homogenize::homogenize;
+use crate::{Mesh, SolutionResults, SolverSettings, Variables, XSData};
+
+// Homogenized cross sections of every node and group. Nodes made of one material are exact,
+// nodes spanning several materials are weighted by their lattice flux.
+struct Node {
+    width: f64,
+    diffusion: Vec<f64>,
+    removal: Vec<f64>,
+    nu_sigf: Vec<f64>,
+    chi: Vec<f64>,
+    // Indexed [from][to], within group scattering is part of the removal
+    scattering: Vec<Vec<f64>>,
+}
+
+impl Node {
+    fn new(meshes: &[Mesh], flux: &[Vec<f32>], xsdata: &XSData, variables: &Variables) -> Self {
+        let constants = homogenize(flux, xsdata, meshes, variables, 0..meshes.len());
+        Node {
+            width: meshes.iter().map(|mesh| mesh.delta_x as f64).sum(),
+            removal: (0..constants.sigt.len())
+                .map(|g| constants.sigt[g] - constants.scatter[g][g])
+                .collect(),
+            scattering: constants
+                .scatter
+                .iter()
+                .enumerate()
+                .map(|(from, row)| {
+                    row.iter()
+                        .enumerate()
+                        .map(|(to, x)| if from == to { 0.0 } else { *x })
+                        .collect()
+                })
+                .collect(),
+            diffusion: constants.diffusion,
+            nu_sigf: constants.nu_sigf,
+            chi: constants.chi,
+        }
+    }
+}
+
+// Flux of a node in an infinite lattice of itself, its meshes alone between reflective
+// boundaries. Nodes of one material need no weights, nodes without fission have no lattice
+// eigenvalue and are volume weighted, both get an empty flux.
+fn lattice_flux(
+    meshes: &[Mesh],
+    xsdata: &XSData,
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> Vec<Vec<f32>> {
+    let mattypes = variables.mattypes as usize;
+    let fissile = meshes.iter().any(|mesh| {
+        (0..variables.energygroups as usize).any(|g| {
+            xsdata.nut[mesh.matid as usize + mattypes * g]
+                * xsdata.sigf[mesh.matid as usize + mattypes * g]
+                > 0.0
+        })
+    });
+    if meshes.iter().all(|mesh| mesh.matid == meshes[0].matid) || !fissile {
+        return Vec::new();
+    }
+    let lattice = Variables {
+        boundl: 1.0,
+        boundr: 1.0,
+        numass: 1,
+        ..variables.clone()
+    };
+    let flux = vec![vec![1.0; meshes.len()]; variables.energygroups as usize];
+    power_iteration(xsdata, meshes, &lattice, settings, 1.0, flux, false).flux
+}
+
+// Quartic expansion of the flux in a node, phi(x) = average + sum a_i P_i(x) for x in [0, 1] with
+// P1 = 2x - 1, P2 = 6x(1 - x) - 1, P3 = 6x(1 - x)(2x - 1), P4 = 6x(1 - x)(5x^2 - 5x + 1)
+#[derive(Clone, Copy)]
+struct Expansion {
+    average: f64,
+    a: [f64; 4],
+}
+
+impl Expansion {
+    fn value(&self, x: f64) -> f64 {
+        let u = x * (1.0 - x);
+        self.average
+            + self.a[0] * (2.0 * x - 1.0)
+            + self.a[1] * (6.0 * u - 1.0)
+            + self.a[2] * 6.0 * u * (2.0 * x - 1.0)
+            + self.a[3] * 6.0 * u * (5.0 * x * x - 5.0 * x + 1.0)
+    }
+
+    // First and second moments of the flux against P1 and P2
+    fn moments(&self) -> (f64, f64) {
+        (
+            self.a[0] / 3.0 + self.a[2] / 5.0,
+            self.a[1] / 5.0 - 3.0 * self.a[3] / 35.0,
+        )
+    }
+}
+
+fn thomas(lower: &[f64], diag: &[f64], upper: &[f64], b: &[f64]) -> Vec<f64> {
+    let n = diag.len();
+    let mut c_prime = vec![0.0; n];
+    let mut x = vec![0.0; n];
+    c_prime[0] = upper[0] / diag[0];
+    x[0] = b[0] / diag[0];
+    for i in 1..n {
+        let denominator = diag[i] - lower[i] * c_prime[i - 1];
+        c_prime[i] = upper[i] / denominator;
+        x[i] = (b[i] - lower[i] * x[i - 1]) / denominator;
+    }
+    for i in (0..n - 1).rev() {
+        x[i] -= c_prime[i] * x[i + 1];
+    }
+    x
+}
+
+// Outgoing current per unit surface flux, J = (1 - albedo) / (2 (1 + albedo)) phi
+fn albedo_coefficient(albedo: f32) -> f64 {
+    0.5 * (1.0 - albedo as f64) / (1.0 + albedo as f64)
+}
+
+// Solves one group for the interface fluxes and updates the node expansions. source holds the
+// node average source and its P1 and P2 moments for every node.
+// Within a node the balance and the weighted residual equations of a3 and a4 are solved together,
+// which leaves the average and every coefficient linear in the two surface fluxes. Lagging a3 and
+// a4 instead diverges once the nodes are several diffusion lengths wide.
+fn solve_group(
+    g: usize,
+    nodes: &[Node],
+    expansions: &mut [Expansion],
+    source: &[(f64, f64, f64)],
+    variables: &Variables,
+) {
+    let m = nodes.len();
+    // average = alpha (left + right) + beta, a3 = tau (right - left) + omega,
+    // a4 = mu (left + right) + nu
+    let mut coefficients: Vec<[f64; 6]> = Vec::with_capacity(m);
+    // Net currents of the node faces, J = c1 left + c2 right + c0
+    let mut left_current: Vec<[f64; 3]> = Vec::with_capacity(m);
+    let mut right_current: Vec<[f64; 3]> = Vec::with_capacity(m);
+    for k in 0..m {
+        let (h, d, removal) = (nodes[k].width, nodes[k].diffusion[g], nodes[k].removal[g]);
+        let (q, s1, s2) = source[k];
+        let leakage = 12.0 * d / h;
+        let odd = 12.0 * d / h.powi(2) + removal / 5.0;
+        let even = 12.0 * d / h.powi(2) + 3.0 * removal / 35.0;
+
+        let kappa = leakage * (1.0 + removal / (5.0 * even));
+        let alpha = kappa / (2.0 * (kappa + h * removal));
+        let beta = (h * q + leakage * s2 / even) / (kappa + h * removal);
+        let tau = -removal / (6.0 * odd);
+        let omega = s1 / odd;
+        let mu = removal / (5.0 * even) * (alpha - 0.5);
+        let nu = (removal * beta / 5.0 - s2) / even;
+        coefficients.push([alpha, beta, tau, omega, mu, nu]);
+
+        let c = d / h;
+        left_current.push([
+            -c * (6.0 * alpha - 4.0 + 6.0 * tau + 6.0 * mu),
+            -c * (6.0 * alpha - 2.0 - 6.0 * tau + 6.0 * mu),
+            -c * (6.0 * beta - 6.0 * omega + 6.0 * nu),
+        ]);
+        right_current.push([
+            -c * (-6.0 * alpha + 2.0 + 6.0 * tau - 6.0 * mu),
+            -c * (-6.0 * alpha + 4.0 - 6.0 * tau - 6.0 * mu),
+            -c * (-6.0 * beta - 6.0 * omega - 6.0 * nu),
+        ]);
+    }
+
+    // Net current continuity at every interface, albedo conditions on the two boundaries
+    let (mut lower, mut diag, mut upper, mut b) = (
+        vec![0.0; m + 1],
+        vec![0.0; m + 1],
+        vec![0.0; m + 1],
+        vec![0.0; m + 1],
+    );
+    diag[0] = left_current[0][0] + albedo_coefficient(variables.boundl);
+    upper[0] = left_current[0][1];
+    b[0] = -left_current[0][2];
+    for s in 1..m {
+        lower[s] = right_current[s - 1][0];
+        diag[s] = right_current[s - 1][1] - left_current[s][0];
+        upper[s] = -left_current[s][1];
+        b[s] = left_current[s][2] - right_current[s - 1][2];
+    }
+    lower[m] = right_current[m - 1][0];
+    diag[m] = right_current[m - 1][1] - albedo_coefficient(variables.boundr);
+    b[m] = -right_current[m - 1][2];
+    let surface = thomas(&lower, &diag, &upper, &b);
+
+    for k in 0..m {
+        let [alpha, beta, tau, omega, mu, nu] = coefficients[k];
+        let (left, right) = (surface[k], surface[k + 1]);
+        let expansion = &mut expansions[k];
+        expansion.average = alpha * (left + right) + beta;
+        expansion.a[0] = 0.5 * (right - left);
+        expansion.a[1] = expansion.average - 0.5 * (left + right);
+        expansion.a[2] = tau * (right - left) + omega;
+        expansion.a[3] = mu * (left + right) + nu;
+    }
+}
+
+// Nodal expansion method with one node per pin or per assembly. The fine mesh flux returned is
+// the nodal expansion evaluated at the centre of every mesh, power normalized.
+pub fn nodal_method(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> SolutionResults {
+    let energygroups = variables.energygroups as usize;
+    let cells = coarse_cells(meshid, &settings.node, variables.numass);
+    // Nodes with the same meshes share their lattice flux
+    let mut lattices: Vec<(&[Mesh], Vec<Vec<f32>>)> = Vec::new();
+    let nodes: Vec<Node> = cells
+        .iter()
+        .map(|&(start, end)| {
+            let meshes = &meshid[start..end];
+            let same = |other: &[Mesh]| {
+                other.len() == meshes.len()
+                    && other
+                        .iter()
+                        .zip(meshes)
+                        .all(|(x, y)| x.matid == y.matid && x.delta_x == y.delta_x)
+            };
+            let index = match lattices.iter().position(|(other, _)| same(other)) {
+                Some(index) => index,
+                None => {
+                    lattices.push((meshes, lattice_flux(meshes, xsdata, variables, settings)));
+                    lattices.len() - 1
+                }
+            };
+            Node::new(meshes, &lattices[index].1, xsdata, variables)
+        })
+        .collect();
+    let m = nodes.len();
+
+    let mut expansions: Vec<Vec<Expansion>> = vec![
+        vec![
+            Expansion {
+                average: 1.0,
+                a: [0.0; 4],
+            };
+            m
+        ];
+        energygroups
+    ];
+    let production = |expansions: &[Vec<Expansion>]| -> f64 {
+        (0..m)
+            .map(|k| {
+                (0..energygroups)
+                    .map(|g| nodes[k].nu_sigf[g] * expansions[g][k].average * nodes[k].width)
+                    .sum::<f64>()
+            })
+            .sum()
+    };
+
+    let mut k_eff: f64 = 1.0;
+    let mut fission_total = production(&expansions);
+    let mut outer = 0;
+    let converged = loop {
+        let previous = expansions.clone();
+        for g in 0..energygroups {
+            // Average and moments of the source of every node. The fission source is lagged
+            // by one outer, the scattering uses the latest flux of the other groups.
+            let source: Vec<(f64, f64, f64)> = (0..m)
+                .map(|k| {
+                    let node = &nodes[k];
+                    (0..energygroups).fold((0.0, 0.0, 0.0), |sum, from| {
+                        let fission = node.chi[g] * node.nu_sigf[from] / k_eff;
+                        let scattering = node.scattering[from][g];
+                        let (f1, f2) = previous[from][k].moments();
+                        let (s1, s2) = expansions[from][k].moments();
+                        (
+                            sum.0
+                                + fission * previous[from][k].average
+                                + scattering * expansions[from][k].average,
+                            sum.1 + fission * f1 + scattering * s1,
+                            sum.2 + fission * f2 + scattering * s2,
+                        )
+                    })
+                })
+                .collect();
+            solve_group(g, &nodes, &mut expansions[g], &source, variables);
+        }
+
+        let new_total = production(&expansions);
+        let new_k = k_eff * new_total / fission_total;
+        let change = expansions
+            .iter()
+            .flatten()
+            .zip(previous.iter().flatten())
+            .map(|(x, y)| ((x.average - y.average) / x.average).abs())
+            .fold(0.0, f64::max);
+        let delta_k = ((new_k - k_eff) / k_eff).abs();
+        k_eff = new_k;
+        fission_total = new_total;
+        outer += 1;
+        if delta_k < settings.k_tolerance as f64 && change < settings.flux_tolerance as f64 {
+            break true;
+        }
+        if outer >= settings.max_outers {
+            break false;
+        }
+    };
+    match converged {
+        true => println!("NEM: {} nodes converged in {} outers", m, outer),
+        false => println!("warning: NEM not converged in {} outers", outer),
+    }
+    println!("{:.10}", k_eff);
+
+    let flux: Vec<Vec<f32>> = (0..energygroups)
+        .map(|g| {
+            let mut flux = vec![0.0; meshid.len()];
+            for (k, &(start, end)) in cells.iter().enumerate() {
+                let mut position = 0.0;
+                for index in start..end {
+                    let centre = (position + 0.5 * meshid[index].delta_x as f64) / nodes[k].width;
+                    flux[index] = expansions[g][k].value(centre) as f32;
+                    position += meshid[index].delta_x as f64;
+                }
+            }
+            flux
+        })
+        .collect();
+    let flux = power_normalize(flux, xsdata, meshid, variables);
+
+    let temp_flux = flux.clone();
+    SolutionResults {
+        flux,
+        assembly_average: average_assembly(temp_flux, variables.numass, variables.energygroups),
+        fission_source: Vec::new(),
+        k: vec![k_eff as f32],
+        k_fund: Vec::new(),
+        multiplication: Vec::new(),
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history: Vec::new(),
+        generation_source: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::{direct_settings, library, slab_meshes, slab_variables};
+    use crate::nodal::nodal_method;
+    use crate::{CoarseMesh, Mesh, SolverSettings, Variables, XSData};
+    use float_cmp::ApproxEq;
+
+    fn slab(boundary: f32) -> (XSData, Vec<Mesh>, Variables) {
+        // One group with D = 1, removal 0.02 and nu sigf 0.03 over 40 cm
+        let xsdata = XSData {
+            sigt: vec![1.0],
+            sigs: vec![0.98],
+            siga: vec![0.02],
+            sigf: vec![0.03],
+            nut: vec![1.0],
+            chit: vec![1.0],
+            scat_matrix: vec![0.98],
+            inv_sigtr: vec![3.0],
+            ..library(1)
+        };
+        let variables = Variables {
+            numass: 4,
+            boundl: boundary,
+            boundr: boundary,
+            ..slab_variables(1, 1)
+        };
+        (xsdata, slab_meshes(40, 1.0, |_| 0), variables)
+    }
+
+    fn assembly_nodes() -> SolverSettings {
+        SolverSettings {
+            node: CoarseMesh::Assembly,
+            flux_tolerance: 1e-6,
+            k_tolerance: 1e-7,
+            max_outers: 10000,
+            ..direct_settings()
+        }
+    }
+
+    #[test]
+    fn test_coarse_nodes() {
+        // Reflected, the flux is flat and k is k infinity
+        let (xsdata, meshid, variables) = slab(1.0);
+        let results = nodal_method(&xsdata, &meshid, &variables, &assembly_nodes());
+        assert!(results.k[0].approx_eq(1.5, (1e-5, 2)));
+
+        // Bare with Marshak conditions, the buckling solves B tan(B L / 2) = 1 / (2 D)
+        let (mut low, mut high) = (0.0_f64, std::f64::consts::PI / 40.0);
+        for _ in 0..100 {
+            let buckling = 0.5 * (low + high);
+            match buckling * (20.0 * buckling).tan() < 0.5 {
+                true => low = buckling,
+                false => high = buckling,
+            }
+        }
+        let exact = 0.03 / (0.02 + low.powi(2));
+        let (xsdata, meshid, variables) = slab(0.0);
+        let results = nodal_method(&xsdata, &meshid, &variables, &assembly_nodes());
+        // Four nodes of 10 cm, about 1.4 diffusion lengths each
+        assert!(((results.k[0] as f64 - exact) / exact).abs() < 1e-4);
+        // The reconstructed flux is symmetric
+        assert!(results.flux[0][0].approx_eq(results.flux[0][39], (1e-4, 2)));
+
+        // A reflected node of 5 cm fuel and 5 cm water is an infinite lattice of fuel slabs. In
+        // one group diffusion the flux is cos(B x) in the fuel and cosh(kappa (10 - x)) in the
+        // water, and D_f B tan(5 B) = D_w kappa tanh(5 kappa) with
+        // B^2 = (nu sigf / k - siga_f) / D_f gives k = 1.8650040. Volume weights give 1.8182.
+        let xsdata = XSData {
+            sigt: vec![1.0, 0.5],
+            sigs: vec![0.9, 0.49],
+            siga: vec![0.1, 0.01],
+            sigf: vec![0.2, 0.0],
+            nut: vec![1.0, 0.0],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![0.9, 0.49],
+            inv_sigtr: vec![1.0, 2.0],
+            ..library(2)
+        };
+        let variables = slab_variables(2, 1);
+        let meshid = slab_meshes(100, 0.1, |x| (x >= 50) as u8);
+        let results = nodal_method(&xsdata, &meshid, &variables, &assembly_nodes());
+        assert!((results.k[0] as f64 - 1.8650040).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,295 @@
+use csv::Writer;
+use std::error::Error;
+
+use crate::discrete::{adjoint_method, group_operators, q_gen, scat_calc, TriDiagonal};
+use crate::{
+    Mesh, Perturbation, PerturbedField, SolutionResults, SolverSettings, Variables, XSData,
+};
+
+// Copies the problem with a second set of materials holding the perturbed cross sections. The
+// meshes inside the perturbed region are moved over to the perturbed materials, so the
+// perturbed operators come from the same matrix_gen, q_gen and scat_calc as the forward ones.
+pub fn perturbed_problem(
+    perturbation: &Perturbation,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> (XSData, Vec<Mesh>, Variables) {
+    let (energygroups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    // Material major fields are laid out [matid + mattypes * group]
+    let extend = |field: &[f32]| -> Vec<f32> {
+        (0..energygroups)
+            .flat_map(|g| {
+                let group = &field[mattypes * g..mattypes * (g + 1)];
+                group
+                    .iter()
+                    .chain(group.iter())
+                    .copied()
+                    .collect::<Vec<f32>>()
+            })
+            .collect()
+    };
+
+    let mut perturbed = XSData {
+        sigt: extend(&xsdata.sigt),
+        sigs: extend(&xsdata.sigs),
+        sigs2: extend(&xsdata.sigs2),
+        sigs3: extend(&xsdata.sigs3),
+        mu: extend(&xsdata.mu),
+        siga: extend(&xsdata.siga),
+        sigf: extend(&xsdata.sigf),
+        nut: extend(&xsdata.nut),
+        chit: extend(&xsdata.chit),
+        chid: match xsdata.chid.is_empty() {
+            true => Vec::new(),
+            false => extend(&xsdata.chid),
+        },
+        beta: xsdata.beta.clone(),
+        lambda: xsdata.lambda.clone(),
+        velocity: xsdata.velocity.clone(),
+        scat_matrix: xsdata
+            .scat_matrix
+            .iter()
+            .chain(xsdata.scat_matrix.iter())
+            .copied()
+            .collect(),
+        inv_sigtr: Vec::new(),
+        source: Vec::new(),
+    };
+
+    for g in 0..energygroups {
+        if perturbation.group != 0 && perturbation.group != g + 1 {
+            continue;
+        }
+        for matid in mattypes..2 * mattypes {
+            let index = matid + 2 * mattypes * g;
+            match perturbation.field {
+                PerturbedField::SigA => {
+                    perturbed.siga[index] += perturbation.delta;
+                    perturbed.sigt[index] += perturbation.delta;
+                }
+                // Fission is part of the absorption
+                PerturbedField::SigF => {
+                    perturbed.sigf[index] += perturbation.delta;
+                    perturbed.siga[index] += perturbation.delta;
+                    perturbed.sigt[index] += perturbation.delta;
+                }
+                PerturbedField::Density => {
+                    let factor = 1.0 + perturbation.delta;
+                    perturbed.sigt[index] *= factor;
+                    perturbed.sigs[index] *= factor;
+                    perturbed.sigs2[index] *= factor;
+                    perturbed.sigs3[index] *= factor;
+                    perturbed.siga[index] *= factor;
+                    perturbed.sigf[index] *= factor;
+                    for to in 0..energygroups {
+                        perturbed.scat_matrix
+                            [energygroups.pow(2) * matid + energygroups * g + to] *= factor;
+                    }
+                }
+            }
+        }
+    }
+    perturbed.inv_sigtr = (0..perturbed.sigt.len())
+        .map(|index| (perturbed.sigt[index] - perturbed.mu[index] * perturbed.sigs[index]).powi(-1))
+        .collect();
+
+    let perturbed_mesh: Vec<Mesh> = meshid
+        .iter()
+        .map(|mesh| {
+            let centre = 0.5 * (mesh.mesh_left + mesh.mesh_right);
+            let inside = centre >= perturbation.left && centre <= perturbation.right;
+            Mesh {
+                matid: match inside {
+                    true => mesh.matid + mattypes as u8,
+                    false => mesh.matid,
+                },
+                delta_x: mesh.delta_x,
+                mesh_left: mesh.mesh_left,
+                mesh_right: mesh.mesh_right,
+            }
+        })
+        .collect();
+
+    let perturbed_variables = Variables {
+        mattypes: 2 * variables.mattypes,
+        ..*variables
+    };
+    (perturbed, perturbed_mesh, perturbed_variables)
+}
+
+// A x in double precision, using the row sums so the removal term is kept on fine meshes
+pub fn apply(a: &TriDiagonal, x: &[f32]) -> Vec<f64> {
+    (0..a.len())
+        .map(|i| {
+            let mut row = a.row_sum[i] as f64 * x[i] as f64;
+            if i > 0 {
+                row += a.lower[i] as f64 * (x[i - 1] as f64 - x[i] as f64);
+            }
+            if i + 1 < a.len() {
+                row += a.upper[i] as f64 * (x[i + 1] as f64 - x[i] as f64);
+            }
+            row
+        })
+        .collect()
+}
+
+// First order reactivity of the perturbation,
+// <adjoint, (dF / k + dS - dA) flux> / <adjoint, F flux>
+fn reactivity(
+    perturbation: &Perturbation,
+    flux: &[Vec<f32>],
+    adjoint: &[Vec<f32>],
+    k: f32,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+) -> f64 {
+    let (perturbed, perturbed_mesh, perturbed_variables) =
+        perturbed_problem(perturbation, xsdata, meshid, variables);
+    let energygroups = variables.energygroups;
+    let n = meshid.len();
+
+    let a_matrix = group_operators(xsdata, meshid, variables);
+    let perturbed_matrix = group_operators(&perturbed, &perturbed_mesh, &perturbed_variables);
+    let q = q_gen(xsdata, energygroups, variables.mattypes, flux, meshid);
+    let perturbed_q = q_gen(
+        &perturbed,
+        energygroups,
+        perturbed_variables.mattypes,
+        flux,
+        &perturbed_mesh,
+    );
+
+    let (mut numerator, mut denominator): (f64, f64) = (0.0, 0.0);
+    for g in 0..energygroups as usize {
+        let delta_a: Vec<f64> = apply(&perturbed_matrix[g], &flux[g])
+            .iter()
+            .zip(apply(&a_matrix[g], &flux[g]))
+            .map(|(x, y)| x - y)
+            .collect();
+        for index in 0..n {
+            let delta_scat = scat_calc(index, &perturbed, &perturbed_mesh, flux, g, energygroups)
+                as f64
+                - scat_calc(index, xsdata, meshid, flux, g, energygroups) as f64;
+            let delta_q = (perturbed_q[g][index] - q[g][index]) as f64;
+            numerator +=
+                adjoint[g][index] as f64 * (delta_q / k as f64 + delta_scat - delta_a[index]);
+            denominator += adjoint[g][index] as f64 * q[g][index] as f64;
+        }
+    }
+    numerator / denominator
+}
+
+// Solves the adjoint problem, writes the importance map and reports the first order reactivity
+// of every perturbation in the input
+pub fn perturbation_analysis(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> Result<(), Box<dyn Error>> {
+    let (adjoint, adjoint_k) = adjoint_method(xsdata, meshid, variables, settings);
+    let k = results.k[0];
+    if ((adjoint_k - k) / k).abs() > 1e-4 {
+        println!(
+            "warning: the adjoint k {:.6} differs from the forward k {:.6}",
+            adjoint_k, k
+        );
+    }
+
+    let mut wtr = Writer::from_path("./importance.csv")?;
+    wtr.write_record(
+        meshid
+            .iter()
+            .map(|mesh| (0.5 * (mesh.mesh_left + mesh.mesh_right)).to_string()),
+    )?;
+    for group in adjoint.iter() {
+        wtr.write_record(group.iter().map(|x| x.to_string()))?;
+    }
+    wtr.flush()?;
+
+    let mut total: f64 = 0.0;
+    for (number, perturbation) in settings.perturbations.iter().enumerate() {
+        let rho = reactivity(
+            perturbation,
+            &results.flux,
+            &adjoint,
+            k,
+            xsdata,
+            meshid,
+            variables,
+        );
+        total += rho;
+        println!(
+            "perturbation {}: {} in group {} over [{}, {}] by {}: {:.2} pcm",
+            number + 1,
+            match perturbation.field {
+                PerturbedField::SigA => "SigA",
+                PerturbedField::SigF => "SigF",
+                PerturbedField::Density => "Density",
+            },
+            perturbation.group,
+            perturbation.left,
+            perturbation.right,
+            perturbation.delta,
+            rho * 1e5
+        );
+    }
+    if settings.perturbations.len() > 1 {
+        println!("total first order reactivity: {:.2} pcm", total * 1e5);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::adjoint_method;
+    use crate::discrete::tests::{direct_settings, library, slab_meshes, slab_variables};
+    use crate::perturbation::{perturbed_problem, reactivity};
+    use crate::{Perturbation, PerturbedField, Variables, XSData};
+
+    #[test]
+    fn test_first_order_reactivity() {
+        // One group slab of alternating fuel and moderator, where the forward and adjoint flux
+        // are the same
+        let xsdata = XSData {
+            sigt: vec![1.0, 1.0],
+            sigs: vec![0.9, 0.98],
+            siga: vec![0.1, 0.02],
+            sigf: vec![0.05, 0.0],
+            nut: vec![2.4, 0.0],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![0.9, 0.98],
+            inv_sigtr: vec![1.0, 1.0],
+            ..library(2)
+        };
+        let meshid = slab_meshes(40, 0.5, |i| (i / 10 % 2) as u8);
+        let variables = Variables {
+            boundr: 0.0,
+            ..slab_variables(2, 1)
+        };
+        let perturbation = Perturbation {
+            field: PerturbedField::SigA,
+            group: 1,
+            left: 0.0,
+            right: 5.0,
+            delta: 0.001,
+        };
+        let settings = direct_settings();
+
+        let (flux, k) = adjoint_method(&xsdata, &meshid, &variables, &settings);
+        let rho = reactivity(&perturbation, &flux, &flux, k, &xsdata, &meshid, &variables);
+
+        // Reference from the perturbed eigenvalue
+        let (perturbed, perturbed_mesh, perturbed_variables) =
+            perturbed_problem(&perturbation, &xsdata, &meshid, &variables);
+        let (_, perturbed_k) =
+            adjoint_method(&perturbed, &perturbed_mesh, &perturbed_variables, &settings);
+        let exact = (1.0 / k - 1.0 / perturbed_k) as f64;
+
+        assert!(rho < 0.0);
+        assert!(((rho - exact) / exact).abs() < 0.05);
+    }
+}
@@ -1,6 +1,6 @@
+use csv::{Writer, WriterBuilder};
 use std::error::Error;
 use std::process::Command;
-use csv::Writer;
 
 use crate::SolutionResults;
 
@@ -13,20 +13,20 @@ pub fn plot_solution(
 ) -> Result<(), Box<dyn Error>> {
     let output_k_fund: Vec<String> = results.k_fund.iter().map(|x| x.to_string()).collect();
     let output_k: Vec<String> = results.k.iter().map(|x| x.to_string()).collect();
-    let mut output_flux =
-        vec![vec!["0.0".to_string(); results.flux[0].len()]; energygroups as usize];
-    for energy in 0..energygroups as usize {
-        for index in 0..results.flux[0].len() {
-            output_flux[energy][index] = results.flux[energy][index].to_string();
-        }
-    }
-    let mut output_average =
-        vec![vec!["0.0".to_string(); results.assembly_average[0].len()]; energygroups as usize];
-    for energy in 0..energygroups as usize {
-        for index in 0..results.assembly_average[0].len() {
-            output_average[energy][index] = results.assembly_average[energy][index].to_string();
-        }
-    }
+    let output_multiplication: Vec<String> = results
+        .multiplication
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    let strings = |values: &[Vec<f32>]| -> Vec<Vec<String>> {
+        values
+            .iter()
+            .take(energygroups as usize)
+            .map(|group| group.iter().map(|x| x.to_string()).collect())
+            .collect()
+    };
+    let output_flux = strings(&results.flux);
+    let output_average = strings(&results.assembly_average);
     let output_fission: Vec<String> = results
         .fission_source
         .iter()
@@ -40,24 +40,56 @@ pub fn plot_solution(
     wtr_vars.write_record([&generations.to_string()])?;
     wtr_vars.flush()?;
 
-    let mut wtr = Writer::from_path("./interface.csv")?;
+    // The rows differ in length, the fission source and k_fund are empty for the diffusion solver
+    let mut wtr = WriterBuilder::new()
+        .flexible(true)
+        .from_path("./interface.csv")?;
 
-    for energy in 0..energygroups as usize {
-        wtr.write_record(&output_flux[energy])?;
-    }
-    for energy in 0..energygroups as usize {
-        wtr.write_record(&output_average[energy])?;
+    for flux in output_flux.iter().chain(output_average.iter()) {
+        wtr.write_record(flux)?;
     }
     wtr.write_record(&output_fission)?;
     wtr.flush()?;
 
-    let mut wtr_k = Writer::from_path("./k_eff.csv")?;
+    let mut wtr_k = WriterBuilder::new()
+        .flexible(true)
+        .from_path("./k_eff.csv")?;
 
     wtr_k.write_record(&output_k)?;
     wtr_k.write_record(&output_k_fund)?;
+    wtr_k.write_record(&output_multiplication)?;
     wtr_k.flush()?;
 
-    Command::new("python3").arg("plot.py").spawn().expect("Command Failed");
+    // Every mode is written as its eigenvalue followed by the flux of each group
+    if !results.modes.is_empty() {
+        let mut wtr_modes = WriterBuilder::new()
+            .flexible(true)
+            .from_path("./modes.csv")?;
+        for (mode, k) in results.modes.iter().zip(results.eigenvalues.iter()) {
+            wtr_modes.write_record([&k.to_string()])?;
+            for flux in mode.iter().take(energygroups as usize) {
+                wtr_modes.write_record(flux.iter().map(|x| x.to_string()))?;
+            }
+        }
+        wtr_modes.flush()?;
+    }
+
+    // One row per outer iteration
+    if !results.history.is_empty() {
+        let mut wtr_history = Writer::from_path("./convergence.csv")?;
+        wtr_history.write_record(["outer", "k", "delta_k", "delta_flux", "delta_source"])?;
+        for (outer, row) in results.history.iter().enumerate() {
+            wtr_history.write_record(
+                std::iter::once((outer + 1).to_string()).chain(row.iter().map(|x| x.to_string())),
+            )?;
+        }
+        wtr_history.flush()?;
+    }
+
+    Command::new("python3")
+        .arg("plot.py")
+        .status()
+        .expect("Command Failed");
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,411 @@
+use csv::{ReaderBuilder, Trim, Writer};
+use nalgebra::{DMatrix, DVector};
+use std::error::Error;
+
+use crate::discrete::{adjoint_method, q_gen};
+use crate::{Mesh, Reactivity, SolutionResults, SolverSettings, Variables, XSData};
+
+// Delayed neutron fraction and decay constant of every precursor group and the prompt neutron
+// generation time in s
+pub struct KineticsParameters {
+    pub beta: Vec<f64>,
+    pub lambda: Vec<f64>,
+    pub generation: f64,
+}
+
+// Power, reactivity and period of every time step, starting from P = 1 at t = 0
+pub struct PowerHistory {
+    pub time: Vec<f64>,
+    pub reactivity: Vec<f64>,
+    pub power: Vec<f64>,
+    pub period: Vec<f64>,
+}
+
+impl Reactivity {
+    pub fn at(&self, time: f64) -> f64 {
+        match self {
+            Reactivity::Step(rho) => *rho as f64,
+            Reactivity::Ramp(rate) => *rate as f64 * time,
+            Reactivity::Sine(amplitude, frequency) => {
+                *amplitude as f64 * (2.0 * std::f64::consts::PI * *frequency as f64 * time).sin()
+            }
+            // Linear between the points and held beyond the last one
+            Reactivity::Table(points) => match points.iter().position(|(t, _)| *t as f64 > time) {
+                Some(0) => points[0].1 as f64,
+                Some(i) => {
+                    let (t0, rho0) = points[i - 1];
+                    let (t1, rho1) = points[i];
+                    rho0 as f64 + (rho1 - rho0) as f64 * (time - t0 as f64) / (t1 - t0) as f64
+                }
+                None => points.last().map_or(0.0, |(_, rho)| *rho as f64),
+            },
+        }
+    }
+}
+
+impl KineticsParameters {
+    // Parameters supplied directly in the input
+    pub fn from_input(xsdata: &XSData, generation: f32) -> Result<Self, Box<dyn Error>> {
+        if xsdata.beta.is_empty() || xsdata.beta.len() != xsdata.lambda.len() {
+            return Err("Beta and Lambda need one value for every precursor group".into());
+        }
+        Ok(KineticsParameters {
+            beta: xsdata.beta.iter().map(|x| *x as f64).collect(),
+            lambda: xsdata.lambda.iter().map(|x| *x as f64).collect(),
+            generation: generation as f64,
+        })
+    }
+
+    // Adjoint weighted parameters of a diffusion solution,
+    //     generation = <adjoint, flux / v> / <adjoint, chi F flux>
+    //     beta_eff = beta <adjoint, chi_d F flux> / <adjoint, chi F flux>
+    pub fn from_diffusion(
+        results: &SolutionResults,
+        xsdata: &XSData,
+        meshid: &[Mesh],
+        variables: &Variables,
+        settings: &SolverSettings,
+    ) -> Result<Self, Box<dyn Error>> {
+        if xsdata.velocity.len() != variables.energygroups as usize {
+            return Err("Velocity needs one value for every energy group".into());
+        }
+        let input = KineticsParameters::from_input(xsdata, 0.0)?;
+        let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+        let (adjoint, _) = adjoint_method(xsdata, meshid, variables, settings);
+        let flux = &results.flux;
+        // q_gen holds chi F flux of every mesh, the delayed one swaps in chi_d
+        let prompt = q_gen(xsdata, energygroups, mattypes, flux, meshid);
+        let delayed_spectrum = XSData {
+            chit: xsdata.chid.clone(),
+            ..xsdata.clone()
+        };
+        let delayed = q_gen(&delayed_spectrum, energygroups, mattypes, flux, meshid);
+
+        let weight = |source: &[Vec<f32>]| -> f64 {
+            source
+                .iter()
+                .flatten()
+                .zip(adjoint.iter().flatten())
+                .map(|(x, y)| *x as f64 * *y as f64)
+                .sum()
+        };
+        let production = weight(&prompt) / results.k[0] as f64;
+        let inverse_speed: f64 = (0..energygroups as usize)
+            .map(|g| {
+                meshid
+                    .iter()
+                    .enumerate()
+                    .map(|(index, mesh)| {
+                        (adjoint[g][index] * flux[g][index] * mesh.delta_x) as f64
+                            / xsdata.velocity[g] as f64
+                    })
+                    .sum::<f64>()
+            })
+            .sum();
+        let effectiveness = weight(&delayed) / weight(&prompt);
+
+        Ok(KineticsParameters {
+            beta: input.beta.iter().map(|x| x * effectiveness).collect(),
+            lambda: input.lambda,
+            generation: inverse_speed / production,
+        })
+    }
+}
+
+// The point kinetics equations dy/dt = M(t) y for y = (P, C_1, ..., C_m)
+//     dP/dt = (rho - beta) / generation P + sum lambda_i C_i
+//     dC_i/dt = beta_i / generation P - lambda_i C_i
+fn kinetics_matrix(parameters: &KineticsParameters, rho: f64) -> DMatrix<f64> {
+    let m = parameters.beta.len();
+    let beta_total: f64 = parameters.beta.iter().sum();
+    let mut matrix = DMatrix::<f64>::zeros(m + 1, m + 1);
+    matrix[(0, 0)] = (rho - beta_total) / parameters.generation;
+    for i in 0..m {
+        matrix[(0, i + 1)] = parameters.lambda[i];
+        matrix[(i + 1, 0)] = parameters.beta[i] / parameters.generation;
+        matrix[(i + 1, i + 1)] = -parameters.lambda[i];
+    }
+    matrix
+}
+
+// Solves (I - factor M) y = rhs
+fn implicit_solve(matrix: &DMatrix<f64>, factor: f64, rhs: &DVector<f64>) -> DVector<f64> {
+    let size = matrix.nrows();
+    (DMatrix::<f64>::identity(size, size) - matrix * factor)
+        .lu()
+        .solve(rhs)
+        .expect("Singular point kinetics step")
+}
+
+// Integrates the point kinetics equations from equilibrium with the L-stable TR-BDF2 method, a
+// trapezoidal stage to t + gamma h followed by a BDF2 stage to t + h. The stiff prompt terms
+// are then damped at any time step.
+pub fn point_kinetics(
+    parameters: &KineticsParameters,
+    reactivity: &Reactivity,
+    time_step: f32,
+    end_time: f32,
+) -> PowerHistory {
+    let gamma = 2.0 - 2.0_f64.sqrt();
+    let h = time_step as f64;
+    let steps = (end_time / time_step).round() as usize;
+    let m = parameters.beta.len();
+
+    let mut y = DVector::<f64>::zeros(m + 1);
+    y[0] = 1.0;
+    for i in 0..m {
+        y[i + 1] = parameters.beta[i] / (parameters.lambda[i] * parameters.generation);
+    }
+
+    let mut history = PowerHistory {
+        time: vec![0.0],
+        reactivity: vec![reactivity.at(0.0)],
+        power: vec![1.0],
+        period: vec![f64::INFINITY],
+    };
+    for step in 0..steps {
+        let time = step as f64 * h;
+        let start = kinetics_matrix(parameters, reactivity.at(time));
+        let middle = kinetics_matrix(parameters, reactivity.at(time + gamma * h));
+        let end = kinetics_matrix(parameters, reactivity.at(time + h));
+
+        let rhs = &y + &start * &y * (0.5 * gamma * h);
+        let y_gamma = implicit_solve(&middle, 0.5 * gamma * h, &rhs);
+        let rhs = (&y_gamma - &y * (1.0 - gamma).powi(2)) / (gamma * (2.0 - gamma));
+        let y_new = implicit_solve(&end, (1.0 - gamma) / (2.0 - gamma) * h, &rhs);
+
+        // Period P / (dP/dt) from the growth over the step
+        let growth = (y_new[0] / y[0]).ln();
+        history.time.push(time + h);
+        history.reactivity.push(reactivity.at(time + h));
+        history.power.push(y_new[0]);
+        history.period.push(match growth.abs() > 1e-12 {
+            true => h / growth,
+            false => f64::INFINITY,
+        });
+        y = y_new;
+    }
+
+    let last = history.power.len() - 1;
+    println!(
+        "point kinetics: beta {:.6}, generation time {:.4e} s, final power {:.6}, period {:.4} s",
+        parameters.beta.iter().sum::<f64>(),
+        parameters.generation,
+        history.power[last],
+        history.period[last]
+    );
+    history
+}
+
+// Reactivity of a measured power or count rate trace. The precursors are integrated exactly for
+// a power that is linear between the points, starting from equilibrium at the first point,
+//     rho = beta + generation (dP/dt - sum lambda_i C_i) / P
+pub fn inverse_kinetics(parameters: &KineticsParameters, time: &[f64], power: &[f64]) -> Vec<f64> {
+    let beta_total: f64 = parameters.beta.iter().sum();
+    let mut precursors: Vec<f64> = parameters
+        .beta
+        .iter()
+        .zip(parameters.lambda.iter())
+        .map(|(beta, lambda)| beta * power[0] / (lambda * parameters.generation))
+        .collect();
+
+    let mut reactivity = vec![0.0];
+    for n in 1..time.len() {
+        let h = time[n] - time[n - 1];
+        let slope = (power[n] - power[n - 1]) / h;
+        let mut delayed = 0.0;
+        for ((c, beta), lambda) in precursors
+            .iter_mut()
+            .zip(parameters.beta.iter())
+            .zip(parameters.lambda.iter())
+        {
+            let decay = (-lambda * h).exp();
+            let source = power[n - 1] * (1.0 - decay) / lambda
+                + slope * (h / lambda - (1.0 - decay) / lambda.powi(2));
+            *c = *c * decay + beta / parameters.generation * source;
+            delayed += lambda * *c;
+        }
+        reactivity.push(beta_total + parameters.generation * (slope - delayed) / power[n]);
+    }
+
+    let last = reactivity.len() - 1;
+    let minimum = reactivity.iter().cloned().fold(f64::INFINITY, f64::min);
+    println!(
+        "inverse kinetics: {} points, final reactivity {:.6} ({:.4} $), minimum {:.6} ({:.4} $)",
+        time.len(),
+        reactivity[last],
+        reactivity[last] / beta_total,
+        minimum,
+        minimum / beta_total
+    );
+    reactivity
+}
+
+// Reactivity of a stable period from the inhour equation
+//     rho = generation / T + sum beta_i / (1 + lambda_i T)
+pub fn inhour(parameters: &KineticsParameters, period: f64) -> f64 {
+    parameters.generation / period
+        + parameters
+            .beta
+            .iter()
+            .zip(parameters.lambda.iter())
+            .map(|(beta, lambda)| beta / (1.0 + lambda * period))
+            .sum::<f64>()
+}
+
+// Time and power columns of a CSV trace, rows that are not two numbers such as a header are
+// skipped
+pub fn read_trace(path: &str) -> Result<(Vec<f64>, Vec<f64>), Box<dyn Error>> {
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .trim(Trim::All)
+        .from_path(path)?;
+    let (mut time, mut power) = (Vec::new(), Vec::new());
+    for record in rdr.records() {
+        let record = record?;
+        if let (Some(Ok(t)), Some(Ok(p))) = (
+            record.get(0).map(|x| x.parse::<f64>()),
+            record.get(1).map(|x| x.parse::<f64>()),
+        ) {
+            time.push(t);
+            power.push(p);
+        }
+    }
+    if time.len() < 2 {
+        return Err(format!("{} needs at least two rows of time and power", path).into());
+    }
+    if power.iter().any(|p| *p <= 0.0) {
+        return Err(format!("{} holds a power that is not positive", path).into());
+    }
+    Ok((time, power))
+}
+
+pub fn write_inverse_kinetics(
+    parameters: &KineticsParameters,
+    time: &[f64],
+    power: &[f64],
+    reactivity: &[f64],
+) -> Result<(), Box<dyn Error>> {
+    let beta_total: f64 = parameters.beta.iter().sum();
+    let mut wtr = Writer::from_path("./inverse_kinetics.csv")?;
+    wtr.write_record(["time", "power", "reactivity", "dollars"])?;
+    for i in 0..time.len() {
+        wtr.write_record([
+            time[i].to_string(),
+            power[i].to_string(),
+            reactivity[i].to_string(),
+            (reactivity[i] / beta_total).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+
+    // Periods from a fraction of a second up to the longest negative period, -1 / lambda_1
+    let periods = [
+        0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, -1000.0,
+        -500.0, -200.0, -100.0,
+    ];
+    let longest = 1.0
+        / parameters
+            .lambda
+            .iter()
+            .cloned()
+            .fold(f64::INFINITY, f64::min);
+    let mut wtr = Writer::from_path("./inhour.csv")?;
+    wtr.write_record(["period", "reactivity", "dollars"])?;
+    for period in periods.iter().filter(|t| **t > 0.0 || -**t > longest) {
+        let rho = inhour(parameters, *period);
+        wtr.write_record([
+            period.to_string(),
+            rho.to_string(),
+            (rho / beta_total).to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+pub fn write_point_kinetics(history: &PowerHistory) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path("./point_kinetics.csv")?;
+    wtr.write_record(["time", "reactivity", "power", "period"])?;
+    for i in 0..history.time.len() {
+        wtr.write_record([
+            (history.time[i] as f32).to_string(),
+            (history.reactivity[i] as f32).to_string(),
+            history.power[i].to_string(),
+            history.period[i].to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::point_kinetics::{inhour, inverse_kinetics, point_kinetics, KineticsParameters};
+    use crate::Reactivity;
+    use float_cmp::ApproxEq;
+
+    fn one_group() -> KineticsParameters {
+        KineticsParameters {
+            beta: vec![0.0065],
+            lambda: vec![0.08],
+            generation: 1e-4,
+        }
+    }
+
+    #[test]
+    fn test_step_insertion() {
+        // With one precursor group the power after a step is the sum of two exponentials,
+        // whose rates are the roots of the inhour equation
+        let parameters = one_group();
+        let (beta, lambda, generation, rho): (f64, f64, f64, f64) = (0.0065, 0.08, 1e-4, 0.002);
+        let history = point_kinetics(&parameters, &Reactivity::Step(rho as f32), 1e-3, 2.0);
+
+        let trace = (rho - beta) / generation - lambda;
+        let det = -lambda * rho / generation;
+        let root = (0.25 * trace * trace - det).sqrt();
+        let (w1, w2) = (0.5 * trace + root, 0.5 * trace - root);
+        let c1 = ((rho - beta) / generation + beta / generation - w2) / (w1 - w2);
+        let exact = |t: f64| c1 * (w1 * t).exp() + (1.0 - c1) * (w2 * t).exp();
+
+        for step in [10, 100, 2000] {
+            let time = history.time[step];
+            assert!(history.power[step].approx_eq(exact(time), (1e-4 * exact(time), 2)));
+        }
+        // The stable period is 1 / w1
+        assert!(history.period[2000].approx_eq(1.0 / w1, (1e-3 / w1, 2)));
+    }
+
+    #[test]
+    fn test_tabulated_ramp() {
+        // A table through the ramp gives the same history as the ramp itself
+        let parameters = one_group();
+        let ramp = point_kinetics(&parameters, &Reactivity::Ramp(0.001), 1e-3, 1.0);
+        let table = point_kinetics(
+            &parameters,
+            &Reactivity::Table(vec![(0.0, 0.0), (2.0, 0.002)]),
+            1e-3,
+            1.0,
+        );
+        assert!(ramp.power[1000].approx_eq(table.power[1000], (1e-9, 2)));
+        assert!(ramp.power[1000] > 1.0);
+    }
+
+    #[test]
+    fn test_inverse_kinetics() {
+        // The reactivity meter recovers the step behind a computed power history, and the
+        // inhour reactivity of its stable period is the step itself
+        let parameters = KineticsParameters {
+            beta: vec![0.000215, 0.001424, 0.001274, 0.002568, 0.000748, 0.000273],
+            lambda: vec![0.0124, 0.0305, 0.111, 0.301, 1.14, 3.01],
+            generation: 4e-5,
+        };
+        let history = point_kinetics(&parameters, &Reactivity::Step(0.001), 1e-2, 300.0);
+        let reactivity = inverse_kinetics(&parameters, &history.time, &history.power);
+        for step in [100, 1000, 30000] {
+            assert!(reactivity[step].approx_eq(0.001, (1e-6, 2)));
+        }
+        let period = history.period[30000];
+        assert!(inhour(&parameters, period).approx_eq(0.001, (1e-6, 2)));
+    }
+}
@@ -0,0 +1,257 @@
+use csv::Writer;
+use std::error::Error;
+use std::ops::Range;
+
+use crate::discrete::assembly_ranges;
+use crate::{Mesh, SolutionResults, Variables, XSData};
+
+// Relative powers of every fuel rod and assembly, normalized to an average of 1, with the
+// standard deviation of the mean over the active Monte Carlo generations (zero for a
+// deterministic solution)
+pub struct PowerDistribution {
+    pub pins: Vec<Range<usize>>, // meshes of every fuel rod
+    pub pin_assembly: Vec<usize>,
+    pub pin_power: Vec<f64>,
+    pub pin_sigma: Vec<f64>,
+    pub assembly_power: Vec<f64>,
+    pub assembly_sigma: Vec<f64>,
+}
+
+impl PowerDistribution {
+    pub fn hot_pin(&self) -> usize {
+        (0..self.pin_power.len())
+            .max_by(|x, y| self.pin_power[*x].total_cmp(&self.pin_power[*y]))
+            .unwrap_or(0)
+    }
+
+    pub fn hot_assembly(&self) -> usize {
+        (0..self.assembly_power.len())
+            .max_by(|x, y| self.assembly_power[*x].total_cmp(&self.assembly_power[*y]))
+            .unwrap_or(0)
+    }
+}
+
+// Fuel rods as the runs of mpfr fuel meshes, fuel_indices is in mesh order
+fn fuel_rods(fuel_indices: &[usize], mpfr: usize) -> Vec<Range<usize>> {
+    let mut rods: Vec<Range<usize>> = Vec::new();
+    for index in fuel_indices {
+        match rods.last_mut() {
+            Some(rod) if rod.end == *index && rod.len() < mpfr => rod.end += 1,
+            _ => rods.push(*index..*index + 1),
+        }
+    }
+    rods
+}
+
+// Mean and standard deviation of the mean of every entry over the samples
+fn statistics(samples: &[Vec<f64>]) -> (Vec<f64>, Vec<f64>) {
+    let n = samples.len() as f64;
+    let mean: Vec<f64> = (0..samples[0].len())
+        .map(|x| samples.iter().map(|sample| sample[x]).sum::<f64>() / n)
+        .collect();
+    let sigma: Vec<f64> = match samples.len() > 1 {
+        true => (0..mean.len())
+            .map(|x| {
+                (samples
+                    .iter()
+                    .map(|sample| (sample[x] - mean[x]).powi(2))
+                    .sum::<f64>()
+                    / (n * (n - 1.0)))
+                    .sqrt()
+            })
+            .collect(),
+        false => vec![0.0; mean.len()],
+    };
+    (mean, sigma)
+}
+
+fn normalize(values: Vec<f64>) -> Vec<f64> {
+    let average = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|x| x / average).collect()
+}
+
+// Pin and assembly powers from the fission rate nu sigf flux integrated over the meshes of
+// every fuel rod. A Monte Carlo solution is normalized generation by generation, which gives
+// the uncertainties, a deterministic one uses its flux.
+pub fn pin_powers(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    fuel_indices: &[usize],
+    variables: &Variables,
+) -> Result<PowerDistribution, Box<dyn Error>> {
+    let (groups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    let pins = fuel_rods(fuel_indices, variables.mpfr);
+    if pins.is_empty() {
+        return Err("no fuel rods to take the power of".into());
+    }
+    let assemblies = assembly_ranges(meshid.len(), variables.numass);
+    let pin_assembly: Vec<usize> = pins
+        .iter()
+        .map(|pin| {
+            assemblies
+                .iter()
+                .position(|range| range.contains(&pin.start))
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let fission_rates: Vec<Vec<f64>> = match results.generation_source.is_empty() {
+        true => vec![(0..meshid.len())
+            .map(|x| {
+                let matid = meshid[x].matid as usize;
+                (0..groups)
+                    .map(|g| {
+                        (xsdata.nut[matid + mattypes * g]
+                            * xsdata.sigf[matid + mattypes * g]
+                            * results.flux[g][x]) as f64
+                    })
+                    .sum()
+            })
+            .collect()],
+        false => results
+            .generation_source
+            .iter()
+            .map(|source| source.iter().map(|x| *x as f64).collect())
+            .collect(),
+    };
+
+    let mut pin_samples: Vec<Vec<f64>> = Vec::new();
+    let mut assembly_samples: Vec<Vec<f64>> = Vec::new();
+    for rate in fission_rates {
+        let pin_rate: Vec<f64> = pins
+            .iter()
+            .map(|pin| {
+                pin.clone()
+                    .map(|x| rate[x] * meshid[x].delta_x as f64)
+                    .sum()
+            })
+            .collect();
+        let mut assembly_rate = vec![0.0; assemblies.len()];
+        for (rate, assembly) in pin_rate.iter().zip(pin_assembly.iter()) {
+            assembly_rate[*assembly] += rate;
+        }
+        if pin_rate.iter().sum::<f64>() <= 0.0 {
+            continue;
+        }
+        pin_samples.push(normalize(pin_rate));
+        // An assembly without fuel takes no part in the average
+        let fuelled: Vec<f64> =
+            normalize(assembly_rate.iter().copied().filter(|x| *x > 0.0).collect());
+        let mut fuelled = fuelled.into_iter();
+        assembly_samples.push(
+            assembly_rate
+                .iter()
+                .map(|x| match *x > 0.0 {
+                    true => fuelled.next().unwrap(),
+                    false => 0.0,
+                })
+                .collect(),
+        );
+    }
+    if pin_samples.is_empty() {
+        return Err("the solution has no fission source".into());
+    }
+
+    let (pin_power, pin_sigma) = statistics(&pin_samples);
+    let (assembly_power, assembly_sigma) = statistics(&assembly_samples);
+    let power = PowerDistribution {
+        pins,
+        pin_assembly,
+        pin_power,
+        pin_sigma,
+        assembly_power,
+        assembly_sigma,
+    };
+
+    let (hot, hot_assembly) = (power.hot_pin(), power.hot_assembly());
+    let centre = 0.5
+        * (meshid[power.pins[hot].start].mesh_left + meshid[power.pins[hot].end - 1].mesh_right);
+    println!(
+        "pin peaking {:.4} +- {:.4} in pin {} of {} at {:.4} cm, assembly {}",
+        power.pin_power[hot],
+        power.pin_sigma[hot],
+        hot + 1,
+        power.pins.len(),
+        centre,
+        power.pin_assembly[hot] + 1
+    );
+    println!(
+        "assembly peaking {:.4} +- {:.4} in assembly {}",
+        power.assembly_power[hot_assembly],
+        power.assembly_sigma[hot_assembly],
+        hot_assembly + 1
+    );
+    Ok(power)
+}
+
+pub fn write_pin_powers(power: &PowerDistribution, meshid: &[Mesh]) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path("./pin_power.csv")?;
+    wtr.write_record(["pin", "assembly", "left", "right", "power", "sigma"])?;
+    for (index, pin) in power.pins.iter().enumerate() {
+        wtr.write_record([
+            (index + 1).to_string(),
+            (power.pin_assembly[index] + 1).to_string(),
+            meshid[pin.start].mesh_left.to_string(),
+            meshid[pin.end - 1].mesh_right.to_string(),
+            power.pin_power[index].to_string(),
+            power.pin_sigma[index].to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+
+    let mut wtr = Writer::from_path("./assembly_power.csv")?;
+    wtr.write_record(["assembly", "power", "sigma"])?;
+    for (index, (power, sigma)) in power
+        .assembly_power
+        .iter()
+        .zip(power.assembly_sigma.iter())
+        .enumerate()
+    {
+        wtr.write_record([
+            (index + 1).to_string(),
+            power.to_string(),
+            sigma.to_string(),
+        ])?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::infinite_medium;
+    use crate::power::pin_powers;
+    use crate::SolutionResults;
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_pin_powers() {
+        // Two rods of 5 meshes in two assemblies with the flux 1 and 3, the rods carry 0.5 and
+        // 1.5 of the average power
+        let (xsdata, meshid, mut variables) = infinite_medium(0.05);
+        variables.numass = 2;
+        let fuel_indices: Vec<usize> = (0..10).collect();
+        let mut results = SolutionResults {
+            flux: vec![[vec![1.0; 5], vec![3.0; 5]].concat()],
+            k: vec![1.0],
+            ..Default::default()
+        };
+        let power = pin_powers(&results, &xsdata, &meshid, &fuel_indices, &variables).unwrap();
+        assert_eq!(power.pins, vec![0..5, 5..10]);
+        assert!(power.pin_power[1].approx_eq(1.5, (1e-6, 2)));
+        assert_eq!(power.hot_pin(), 1);
+        assert!(power.assembly_power[0].approx_eq(0.5, (1e-6, 2)));
+        assert!(power.pin_sigma[1].approx_eq(0.0, (1e-12, 2)));
+
+        // Two generations with the second rod at 1.4 and 1.6 give 1.5 +- 0.1
+        results.generation_source = vec![
+            [vec![0.6; 5], vec![1.4; 5]].concat(),
+            [vec![0.4; 5], vec![1.6; 5]].concat(),
+        ];
+        let power = pin_powers(&results, &xsdata, &meshid, &fuel_indices, &variables).unwrap();
+        assert!(power.pin_power[1].approx_eq(1.5, (1e-6, 2)));
+        assert!(power.pin_sigma[1].approx_eq(0.1, (1e-6, 2)));
+    }
+}
@@ -1,7 +1,12 @@
 use memmap2::MmapOptions;
+use std::error::Error;
 use std::fs::File;
+use std::str::FromStr;
 
-use crate::{DeltaX, Solver, Variables, XSData};
+use crate::{
+    Acceleration, CoarseMesh, DeltaX, Method, Perturbation, PerturbedField, Preconditioner, Ramp,
+    Reactivity, Search, SearchParameter, Solver, SolverSettings, Sweep, Variables, XSData,
+};
 
 fn skip_line(mut pos: usize, end: usize, buffer: &[u8]) -> usize {
     while buffer[pos] != b'\n' && pos < end {
@@ -11,7 +16,7 @@ fn skip_line(mut pos: usize, end: usize, buffer: &[u8]) -> usize {
 }
 
 fn get_index(key: &str, length: usize) -> usize {
-    return match (key, length) {
+    match (key, length) {
         ("lk", 5) => 0,
         ("es", 8) => 1,
         ("ps", 12) => 2,
@@ -37,14 +42,137 @@ fn get_index(key: &str, length: usize) -> usize {
         ("id", 5) => 22,
         ("on", 8) => 23,
         ("er", 6) => 24,
-        _ => 25,
+        ("ol", 8) => 25,
+        ("er", 8) => 26,
+        ("ga", 5) => 27,
+        ("nd", 7) => 28,
+        ("rt", 7) => 29,
+        ("le", 5) => 30,
+        ("el", 5) => 31,
+        ("ft", 5) => 32,
+        ("fd", 4) => 33,
+        ("ep", 8) => 34,
+        ("ce", 6) => 35,
+        ("ly", 8) => 36,
+        ("nt", 7) => 37,
+        ("rb", 7) => 38,
+        ("es", 5) => 39,
+        ("de", 4) => 40,
+        ("sn", 2) => 41,
+        ("ep", 5) => 42,
+        ("s2", 5) => 43,
+        ("s3", 5) => 44,
+        ("ol", 7) => 45,
+        ("ol", 9) => 46,
+        ("ol", 4) => 47,
+        ("rs", 9) => 48,
+        ("ol", 5) => 49,
+        ("up", 5) => 50,
+        ("ce", 9) => 51,
+        ("ta", 4) => 52,
+        ("da", 6) => 53,
+        ("id", 4) => 54,
+        ("ty", 8) => 55,
+        ("at", 6) => 56,
+        ("me", 7) => 57,
+        ("ta", 5) => 58,
+        ("mp", 4) => 59,
+        ("ho", 3) => 60,
+        ("on", 10) => 61,
+        ("ce", 5) => 62,
+        ("ch", 6) => 63,
+        ("tk", 7) => 64,
+        ("se", 8) => 65,
+        ("ed", 9) => 66,
+        ("ze", 10) => 67,
+        ("ph", 3) => 68,
+        ("ng", 7) => 69,
+        ("ce", 7) => 70,
+        _ => 71,
+    }
+}
+
+// Value at index of the words of a keyword line
+fn word<T: FromStr>(line: &[&str], index: usize, keyword: &str) -> Result<T, Box<dyn Error>> {
+    let word = line
+        .get(index)
+        .ok_or(format!("{} has too few values", keyword))?;
+    word.parse()
+        .map_err(|_| format!("{} value {} cannot be read", keyword, word).into())
+}
+
+// Field, group, left and right edges and the change of a cross section perturbation
+fn perturbation(line: &[&str]) -> Result<Perturbation, Box<dyn Error>> {
+    Ok(Perturbation {
+        field: match line[0].to_ascii_lowercase().as_str() {
+            "siga" => PerturbedField::SigA,
+            "sigf" => PerturbedField::SigF,
+            "density" => PerturbedField::Density,
+            field => return Err(format!("Unknown perturbed field {}", field).into()),
+        },
+        group: word(line, 1, "Perturb")?,
+        left: word(line, 2, "Perturb")?,
+        right: word(line, 3, "Perturb")?,
+        delta: word(line, 4, "Perturb")?,
+    })
+}
+
+// Perturbation followed by the start and end time of its ramp
+fn ramp(line: &[&str]) -> Result<Ramp, Box<dyn Error>> {
+    Ok(Ramp {
+        perturbation: perturbation(line)?,
+        start: word(line, 5, "Ramp")?,
+        end: word(line, 6, "Ramp")?,
+    })
+}
+
+// Step, ramp or sine reactivity followed by its values, or a table of time and reactivity pairs
+fn reactivity(line: &str) -> Result<Reactivity, Box<dyn Error>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let value = |index: usize| -> Result<f32, Box<dyn Error>> { word(&words, index, "Rho") };
+    Ok(
+        match words.first().map(|x| x.to_ascii_lowercase()).as_deref() {
+            None => Reactivity::Step(0.0),
+            Some("step") => Reactivity::Step(value(1)?),
+            Some("ramp") => Reactivity::Ramp(value(1)?),
+            Some("sine") => Reactivity::Sine(value(1)?, value(2)?),
+            Some("table") => Reactivity::Table(
+                (1..words.len())
+                    .step_by(2)
+                    .map(|index| Ok((value(index)?, value(index + 1)?)))
+                    .collect::<Result<_, Box<dyn Error>>>()?,
+            ),
+            Some(kind) => return Err(format!("Unknown reactivity {}", kind).into()),
+        },
+    )
+}
+
+// Parameter, with the material for siga and rods, followed by the low and high end of the
+// search range
+fn search(line: &str, target: f32) -> Result<Option<Search>, Box<dyn Error>> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some(kind) = words.first() else {
+        return Ok(None);
+    };
+    let (parameter, range) = match kind.to_ascii_lowercase().as_str() {
+        "siga" => (SearchParameter::SigA(word(&words, 1, "Search")?), 2),
+        "rodpitch" => (SearchParameter::RodPitch, 1),
+        "rods" => (SearchParameter::Rods(word(&words, 1, "Search")?), 2),
+        "buckling" => (SearchParameter::Buckling, 1),
+        kind => return Err(format!("Unknown search parameter {}", kind).into()),
     };
+    Ok(Some(Search {
+        parameter,
+        low: word(&words, range, "Search")?,
+        high: word(&words, range + 1, "Search")?,
+        target,
+    }))
 }
 
-fn scan_ascii_chunk(buffer: &[u8]) -> [String; 26] {
+fn scan_ascii_chunk(buffer: &[u8]) -> [String; 72] {
     let end = buffer.len();
 
-    let mut temp: [String; 26] = Default::default();
+    let mut temp: [String; 72] = std::array::from_fn(|_| String::new());
 
     let mut pos: usize = 0;
     let mut line_start: usize = 0;
@@ -71,7 +199,6 @@ fn scan_ascii_chunk(buffer: &[u8]) -> [String; 26] {
                         .to_string();
                     let length = key.len();
                     temp[get_index(&key[length - 2..length], length)] += &(" ".to_owned() + &value);
-                } else {
                 }
                 line_start = pos + 1;
             }
@@ -82,7 +209,11 @@ fn scan_ascii_chunk(buffer: &[u8]) -> [String; 26] {
     temp
 }
 
-pub fn process_input() -> (Variables, XSData, Vec<u8>, DeltaX, bool, Solver) {
+// Everything read from the deck, the material of every cell and the solution method with its
+// settings
+type Input = (Variables, XSData, Vec<u8>, DeltaX, Method, SolverSettings);
+
+pub fn process_input() -> Result<Input, Box<dyn Error>> {
     let file = File::open("./TestCaseC.txt").expect("Unable to read the file");
     let mapped_file = unsafe { MmapOptions::new().map(&file).unwrap() };
     let start: usize = 0;
@@ -121,6 +252,14 @@ pub fn process_input() -> (Variables, XSData, Vec<u8>, DeltaX, bool, Solver) {
             .split_whitespace()
             .map(|x| x.parse().unwrap())
             .collect(),
+        sigs2: temp[43]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+        sigs3: temp[44]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
         mu: temp[16]
             .split_whitespace()
             .map(|x| x.parse().unwrap())
@@ -141,14 +280,47 @@ pub fn process_input() -> (Variables, XSData, Vec<u8>, DeltaX, bool, Solver) {
             .split_whitespace()
             .map(|x| x.parse().unwrap())
             .collect(),
+        chid: temp[54]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+        beta: temp[52]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+        lambda: temp[53]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+        velocity: temp[55]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
         // Index via [(mattype * energygroups) + ((energygroups * starting_energy) + final_energy)]
         scat_matrix: temp[21]
             .split_whitespace()
             .map(|x| x.parse().unwrap())
             .collect(),
         inv_sigtr: Vec::new(),
+        source: temp[35]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
     };
 
+    // The higher scattering moments are optional, without them SP3 falls back to isotropic
+    // within group scattering
+    for moment in [&mut xsdata.sigs2, &mut xsdata.sigs3] {
+        if moment.is_empty() {
+            *moment = vec![0.0; xsdata.sigt.len()];
+        }
+    }
+
+    // Without a delayed spectrum the delayed neutrons are born with the fission spectrum
+    if xsdata.chid.is_empty() {
+        xsdata.chid = xsdata.chit.clone();
+    }
+
     for index in 0..xsdata.sigt.len() {
         xsdata
             .inv_sigtr
@@ -159,17 +331,145 @@ pub fn process_input() -> (Variables, XSData, Vec<u8>, DeltaX, bool, Solver) {
         .split_ascii_whitespace()
         .map(|x| x.parse::<u8>().unwrap())
         .collect();
-    (
-        variables,
-        xsdata,
-        matid,
-        deltax,
-        temp[23].trim().parse::<u8>().unwrap() != 1,
-        match temp[24].trim() {
+    let method = match temp[23].trim() {
+        "1" => Method::MonteCarlo,
+        "2" => Method::FixedSource,
+        "3" => Method::Nodal,
+        "4" => Method::Transport,
+        "5" => Method::SP3,
+        "6" => Method::Kinetics,
+        "7" => Method::PointKinetics,
+        "8" => Method::InverseKinetics,
+        _ => Method::Diffusion,
+    };
+    let settings = SolverSettings {
+        solver: match temp[24].trim() {
             "1" => Solver::Gaussian,
             "2" => Solver::Jacobian,
             "3" => Solver::SR,
+            "4" => Solver::ConjugateGradient,
+            "5" => Solver::BiCGStab,
+            "6" => Solver::Gmres,
+            "7" => Solver::Multigrid,
             _ => Solver::LinAlg,
         },
-    )
+        // The inner controls are optional and only used by the iterative solvers
+        inner_tolerance: temp[25].trim().parse().unwrap_or(1e-6),
+        max_inner: temp[26].trim().parse().unwrap_or(1000),
+        omega: temp[27].trim().parse().unwrap_or(0.0),
+        preconditioner: match temp[28].trim() {
+            "0" => Preconditioner::Identity,
+            "1" => Preconditioner::Jacobi,
+            _ => Preconditioner::Ilu0,
+        },
+        restart: temp[29].trim().parse().unwrap_or(30),
+        cycle: temp[30].trim().parse().unwrap_or(1),
+        acceleration: match temp[31].trim() {
+            "1" => Acceleration::Wielandt,
+            "2" => Acceleration::Chebyshev,
+            "3" => Acceleration::Aitken,
+            _ => Acceleration::None,
+        },
+        shift: temp[32].trim().parse().unwrap_or(0.1),
+        cmfd: match temp[33].trim() {
+            "1" => CoarseMesh::Pin,
+            "2" => CoarseMesh::Assembly,
+            _ => CoarseMesh::None,
+        },
+        cmfd_interval: temp[34].trim().parse().unwrap_or(3),
+        multiply: temp[36].trim() != "0",
+        adjoint: temp[37].trim() == "1",
+        // Every Perturb line holds the field, group, left and right edges and the change
+        perturbations: temp[38]
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .chunks(5)
+            .map(perturbation)
+            .collect::<Result<_, _>>()?,
+        modes: temp[39].trim().parse().unwrap_or(1),
+        node: match temp[40].trim() {
+            "2" => CoarseMesh::Assembly,
+            _ => CoarseMesh::Pin,
+        },
+        sn: temp[41].trim().parse().unwrap_or(8),
+        sweep: match temp[42].trim() {
+            "1" => Sweep::StepCharacteristic,
+            _ => Sweep::DiamondDifference,
+        },
+        flux_tolerance: temp[45].trim().parse().unwrap_or(1e-5),
+        source_tolerance: temp[46].trim().parse().unwrap_or(1e-5),
+        k_tolerance: temp[47].trim().parse().unwrap_or(1e-6),
+        max_outers: temp[48].trim().parse().unwrap_or(1000),
+        upscatter_tolerance: temp[49].trim().parse().unwrap_or(1e-5),
+        max_upscatter: temp[50].trim().parse().unwrap_or(50),
+        rebalance: temp[51].trim() == "1",
+        time_step: temp[56].trim().parse().unwrap_or(1e-3),
+        end_time: temp[57].trim().parse().unwrap_or(1.0),
+        theta: temp[58].trim().parse().unwrap_or(0.5),
+        // Every Ramp line holds a Perturb line followed by the start and end times
+        ramps: temp[59]
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .chunks(7)
+            .map(ramp)
+            .collect::<Result<_, _>>()?,
+        reactivity: reactivity(&temp[60])?,
+        generation: temp[61].trim().parse().unwrap_or(0.0),
+        trace: temp[62].trim().to_string(),
+        search: search(&temp[63], temp[64].trim().parse().unwrap_or(1.0))?,
+        collapse: temp[65]
+            .split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect(),
+        current_weighted: temp[66].trim() == "1",
+        homogenize: temp[67].trim() == "1",
+        sph: match temp[68].trim() {
+            "1" => CoarseMesh::Pin,
+            "2" => CoarseMesh::Assembly,
+            _ => CoarseMesh::None,
+        },
+        peaking: temp[69].trim() == "1",
+        balance: temp[70].trim() == "1",
+    };
+
+    // Every direction of the S_N quadrature is paired with its mirror
+    if settings.sn < 2 || !settings.sn.is_multiple_of(2) {
+        return Err(format!("SN = {} has to be even and at least 2", settings.sn).into());
+    }
+    if settings.restart < 1 {
+        return Err("Restart has to be at least 1".into());
+    }
+    if settings.time_step <= 0.0 || !settings.time_step.is_finite() {
+        return Err(format!("DeltaT = {} has to be positive", settings.time_step).into());
+    }
+    if settings.end_time < 0.0 || !settings.end_time.is_finite() {
+        return Err(format!("EndTime = {} cannot be negative", settings.end_time).into());
+    }
+    if !(settings.theta > 0.0 && settings.theta <= 1.0) {
+        return Err(format!("Theta = {} has to be in (0, 1]", settings.theta).into());
+    }
+
+    Ok((variables, xsdata, matid, deltax, method, settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::process_input::{perturbation, ramp, reactivity, search};
+
+    #[test]
+    fn test_malformed_lines() {
+        // A short or unreadable line is an error rather than a panic
+        assert!(perturbation(&["siga", "1", "0.0", "10.0", "0.01"]).is_ok());
+        assert!(perturbation(&["siga", "1", "0.0"]).is_err());
+        assert!(perturbation(&["siga", "one", "0.0", "10.0", "0.01"]).is_err());
+        assert!(perturbation(&["sigt", "1", "0.0", "10.0", "0.01"]).is_err());
+        assert!(ramp(&["siga", "1", "0.0", "10.0", "0.01", "0.0", "1.0"]).is_ok());
+        assert!(ramp(&["siga", "1", "0.0", "10.0", "0.01", "0.0"]).is_err());
+        assert!(reactivity("table 0.0 0.0 1.0 0.001").is_ok());
+        assert!(reactivity("table 0.0 0.0 1.0").is_err());
+        assert!(reactivity("sine 0.001").is_err());
+        assert!(search("", 1.0).unwrap().is_none());
+        assert!(search("rods 3 0 10", 1.0).unwrap().is_some());
+        assert!(search("rods 0 10", 1.0).is_err());
+    }
 }
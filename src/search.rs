@@ -0,0 +1,293 @@
+use csv::Writer;
+use std::error::Error;
+
+use crate::discrete::nalgebra_method;
+use crate::nodal::nodal_method;
+use crate::sp3::sp3_method;
+use crate::transport::transport_method;
+use crate::{
+    mesh_gen, DeltaX, Mesh, Method, Search, SearchParameter, SolverSettings, Variables, XSData,
+};
+
+// Largest |k - target| of a critical configuration and the limit of eigenvalue solves
+const SEARCH_TOLERANCE: f64 = 1e-5;
+const MAX_SOLVES: usize = 40;
+
+pub struct SearchResults {
+    pub value: f64,             // critical value of the searched parameter
+    pub table: Vec<(f64, f64)>, // parameter value and k of every solve
+}
+
+// Problem with the searched parameter set to value. Meshes are rebuilt from the MatID layout
+// as the rod pitch and the control rod cells change the geometry.
+fn search_problem(
+    parameter: &SearchParameter,
+    value: f64,
+    xsdata: &XSData,
+    matid: &[u8],
+    variables: &Variables,
+) -> (XSData, Vec<Mesh>, Variables) {
+    let mut xsdata = xsdata.clone();
+    let mut matid = matid.to_vec();
+    let mut variables = variables.clone();
+    let mattypes = variables.mattypes as usize;
+    let value = value as f32;
+
+    match parameter {
+        // Scaling the absorption scales the total cross section with it
+        SearchParameter::SigA(material) => {
+            for index in (*material as usize..xsdata.siga.len()).step_by(mattypes) {
+                let delta = (value - 1.0) * xsdata.siga[index];
+                xsdata.siga[index] += delta;
+                xsdata.sigt[index] += delta;
+                xsdata.inv_sigtr[index] =
+                    (xsdata.sigt[index] - xsdata.mu[index] * xsdata.sigs[index]).powi(-1);
+            }
+        }
+        // Variables hold the water gap between the rods rather than the pitch
+        SearchParameter::RodPitch => variables.rodpitch = value - variables.roddia,
+        // Rods go into the cells of materials without fission
+        SearchParameter::Rods(material) => {
+            let count = value.round() as usize;
+            let fissile = |matid: u8| {
+                (0..variables.energygroups as usize).any(|g| {
+                    let index = matid as usize + mattypes * g;
+                    xsdata.nut[index] * xsdata.sigf[index] > 0.0
+                })
+            };
+            matid
+                .iter_mut()
+                .filter(|x| !fissile(**x))
+                .take(count)
+                .for_each(|x| *x = *material);
+        }
+        // Transverse leakage D B^2 as an extra absorption, the diffusion coefficient is kept
+        SearchParameter::Buckling => {
+            for index in 0..xsdata.siga.len() {
+                let leakage = xsdata.inv_sigtr[index] / 3.0 * value;
+                xsdata.siga[index] += leakage;
+                xsdata.sigt[index] += leakage;
+            }
+        }
+    }
+
+    let deltax = DeltaX {
+        fuel: variables.roddia / variables.mpfr as f32,
+        water: variables.rodpitch / variables.mpwr as f32,
+    };
+    let (meshid, _) = mesh_gen(matid, &variables, &deltax);
+    (xsdata, meshid, variables)
+}
+
+fn eigenvalue(
+    method: &Method,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> f64 {
+    let results = match method {
+        Method::Nodal => nodal_method(xsdata, meshid, variables, settings),
+        Method::Transport => transport_method(xsdata, meshid, variables, settings),
+        Method::SP3 => sp3_method(xsdata, meshid, variables, settings),
+        _ => nalgebra_method(xsdata, meshid, variables, settings),
+    };
+    results.k[0] as f64
+}
+
+// Brent's method on k(x) - target over a bracket [a, b] with a sign change, inverse quadratic
+// or secant steps with a fall back to bisection
+fn brent(
+    mut k: impl FnMut(f64) -> f64,
+    target: f64,
+    mut a: f64,
+    mut b: f64,
+) -> Result<f64, Box<dyn Error>> {
+    let (mut fa, mut fb) = (k(a) - target, k(b) - target);
+    if fa * fb > 0.0 {
+        return Err(format!(
+            "k - target has the same sign at {} and {} ({:.6}, {:.6}), widen the search range",
+            a, b, fa, fb
+        )
+        .into());
+    }
+    let (mut c, mut fc) = (a, fa);
+    let (mut d, mut e) = (b - a, b - a);
+    for _ in 2..MAX_SOLVES {
+        if fb.abs() < SEARCH_TOLERANCE {
+            return Ok(b);
+        }
+        if fb * fc > 0.0 {
+            (c, fc) = (a, fa);
+            (d, e) = (b - a, b - a);
+        }
+        if fc.abs() < fb.abs() {
+            (a, fa) = (b, fb);
+            (b, fb) = (c, fc);
+            (c, fc) = (a, fa);
+        }
+        let half = 0.5 * (c - b);
+        if half.abs() < 1e-7 * b.abs().max(1e-7) {
+            return Ok(b);
+        }
+        if e.abs() >= 1e-12 && fa.abs() > fb.abs() {
+            let s = fb / fa;
+            let (mut p, mut q) = match a == c {
+                true => (2.0 * half * s, 1.0 - s),
+                false => {
+                    let (q, r) = (fa / fc, fb / fc);
+                    (
+                        s * (2.0 * half * q * (q - r) - (b - a) * (r - 1.0)),
+                        (q - 1.0) * (r - 1.0) * (s - 1.0),
+                    )
+                }
+            };
+            if p > 0.0 {
+                q = -q;
+            }
+            p = p.abs();
+            match 2.0 * p < (3.0 * half * q).abs().min((e * q).abs()) {
+                true => (e, d) = (d, p / q),
+                false => (e, d) = (half, half),
+            }
+        } else {
+            (e, d) = (half, half);
+        }
+        (a, fa) = (b, fb);
+        b += d;
+        fb = k(b) - target;
+    }
+    Err(format!("no critical value within {} solves", MAX_SOLVES).into())
+}
+
+// Adjusts one input parameter until k reaches the target, recording k of every solve. The
+// control rod cell count is an integer and is bisected down to neighbouring counts instead,
+// the critical value is then interpolated between them.
+pub fn critical_search(
+    search: &Search,
+    method: &Method,
+    xsdata: &XSData,
+    matid: &[u8],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> Result<SearchResults, Box<dyn Error>> {
+    if let Method::MonteCarlo | Method::FixedSource = method {
+        return Err("the search needs a deterministic eigenvalue solution".into());
+    }
+    let target = search.target as f64;
+    let mut table: Vec<(f64, f64)> = Vec::new();
+    let mut k = |value: f64| -> f64 {
+        let (xsdata, meshid, variables) =
+            search_problem(&search.parameter, value, xsdata, matid, variables);
+        let k = eigenvalue(method, &xsdata, &meshid, &variables, settings);
+        table.push((value, k));
+        k
+    };
+
+    let value = match search.parameter {
+        SearchParameter::Rods(_) => {
+            let (mut low, mut high) = (search.low.round() as i64, search.high.round() as i64);
+            let (mut k_low, mut k_high) = (k(low as f64) - target, k(high as f64) - target);
+            if k_low * k_high > 0.0 {
+                return Err(format!(
+                    "k - target has the same sign with {} and {} rod cells, widen the search range",
+                    low, high
+                )
+                .into());
+            }
+            while high - low > 1 {
+                let middle = (low + high) / 2;
+                let k_middle = k(middle as f64) - target;
+                match k_middle * k_low > 0.0 {
+                    true => (low, k_low) = (middle, k_middle),
+                    false => (high, k_high) = (middle, k_middle),
+                }
+            }
+            low as f64 + (high - low) as f64 * k_low / (k_low - k_high)
+        }
+        _ => brent(&mut k, target, search.low as f64, search.high as f64)?,
+    };
+
+    println!(
+        "critical search: value {:.6} for k {:.6} after {} solves",
+        value,
+        target,
+        table.len()
+    );
+    Ok(SearchResults { value, table })
+}
+
+// k against the parameter, the critical value goes in the last row
+pub fn write_search(results: &SearchResults) -> Result<(), Box<dyn Error>> {
+    let mut table = results.table.clone();
+    table.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let mut wtr = Writer::from_path("./search.csv")?;
+    wtr.write_record(["value", "k"])?;
+    for (value, k) in table {
+        wtr.write_record([value.to_string(), k.to_string()])?;
+    }
+    wtr.write_record(["critical".to_string(), results.value.to_string()])?;
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::{direct_settings, infinite_medium, library, slab_variables};
+    use crate::search::{critical_search, search_problem};
+    use crate::{Method, Search, SearchParameter, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_infinite_medium_search() {
+        // With nu sigf = 0.2 and siga = 0.1 k infinity is 2, so the critical absorption
+        // multiplier is 2 and the critical buckling solves 0.2 / (0.1 + B^2 / 3) = 1
+        let (xsdata, _, mut variables) = infinite_medium(0.2);
+        (variables.mpfr, variables.mpwr) = (5, 4);
+        let matid = vec![0; 3];
+        for (parameter, low, high, critical) in [
+            (SearchParameter::SigA(0), 1.0, 4.0, 2.0),
+            (SearchParameter::Buckling, 0.0, 1.0, 0.3),
+        ] {
+            let search = Search {
+                parameter,
+                low,
+                high,
+                target: 1.0,
+            };
+            let results = critical_search(
+                &search,
+                &Method::Diffusion,
+                &xsdata,
+                &matid,
+                &variables,
+                &direct_settings(),
+            )
+            .unwrap();
+            assert!(results.value.approx_eq(critical, (1e-4, 2)));
+            let (_, k) = results.table[results.table.len() - 1];
+            assert!(k.approx_eq(1.0, (1e-5, 2)));
+        }
+    }
+
+    #[test]
+    fn test_rod_cells() {
+        // Material 2 is the fuel and 0 the water, the control rod material 1 only replaces
+        // water cells
+        let xsdata = XSData {
+            nut: vec![0.0, 0.0, 2.5],
+            sigf: vec![0.0, 0.0, 0.1],
+            ..library(3)
+        };
+        let variables = slab_variables(3, 1);
+        let matid = vec![0, 2, 0, 2, 0];
+        let (_, meshid, _) =
+            search_problem(&SearchParameter::Rods(1), 2.0, &xsdata, &matid, &variables);
+        let count = |material: u8| meshid.iter().filter(|mesh| mesh.matid == material).count();
+        let (_, reference, _) =
+            search_problem(&SearchParameter::Rods(1), 0.0, &xsdata, &matid, &variables);
+        let reference = reference.iter().filter(|mesh| mesh.matid == 2).count();
+        assert_eq!(count(2), reference);
+        assert!(count(1) > 0);
+    }
+}
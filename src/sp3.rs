@@ -0,0 +1,268 @@
+use crate::discrete::{
+    average_assembly, power_normalize, q_gen, scat_calc, source_residual, InnerSolver, TriDiagonal,
+};
+use crate::{Mesh, SolutionResults, SolverSettings, Variables, XSData};
+
+// Limit of the passes over the two coupled equations of a group within one outer
+const COUPLING_ITERATIONS: usize = 50;
+
+// Leakage through a boundary face of the two SP3 equations as a linear function of the
+// (Phi0, phi2) cell values. The face values follow from the Marshak conditions
+//     D0 dPhi0/dn = a (Phi0 / 2 - 3 phi2 / 8)
+//     D2 dphi2/dn = a (-Phi0 / 8 + 7 phi2 / 8)
+// with a = (1 - albedo) / (1 + albedo), which is zero for a reflective boundary.
+fn marshak(d0: f32, d2: f32, albedo: f32) -> [[f32; 2]; 2] {
+    let a = (1.0 - albedo) / (1.0 + albedo);
+    let b = [[d0 + 0.5 * a, -0.375 * a], [-0.125 * a, d2 + 0.875 * a]];
+    let det = b[0][0] * b[1][1] - b[0][1] * b[1][0];
+    // leakage = diag(d) (c - b^-1 diag(d) c)
+    [
+        [d0 - d0 * b[1][1] * d0 / det, d0 * b[0][1] * d2 / det],
+        [d2 * b[1][0] * d0 / det, d2 - d2 * b[0][0] * d2 / det],
+    ]
+}
+
+// Three point operator of one SP3 equation, with diffusion coefficient and removal per mesh.
+// The boundary leakage of the equation itself goes on the diagonal, the coupling to the other
+// equation is left to the source.
+fn sp3_matrix(
+    diffusion: &[f32],
+    removal: &[f32],
+    meshid: &[Mesh],
+    left: f32,
+    right: f32,
+) -> TriDiagonal {
+    let n = meshid.len();
+    let mut a = TriDiagonal::new(n);
+    for x in 0..n {
+        a.row_sum[x] = removal[x] * meshid[x].delta_x;
+        a.diag[x] = a.row_sum[x];
+    }
+    for x in 0..n - 1 {
+        let d_curr = diffusion[x] / meshid[x].delta_x;
+        let d_next = diffusion[x + 1] / meshid[x + 1].delta_x;
+        let d_nextcurr = 2.0 * d_curr * d_next / (d_curr + d_next);
+        a.upper[x] = -d_nextcurr;
+        a.lower[x + 1] = -d_nextcurr;
+        a.diag[x] += d_nextcurr;
+        a.diag[x + 1] += d_nextcurr;
+    }
+    a.row_sum[0] += left;
+    a.diag[0] += left;
+    a.row_sum[n - 1] += right;
+    a.diag[n - 1] += right;
+    a
+}
+
+// Simplified P3 solution. Phi0 = phi0 + 2 phi2 and phi2 of every group satisfy
+//     -d/dx D0 dPhi0/dx + sig0 (Phi0 - 2 phi2) = Q
+//     -d/dx D2 dphi2/dx + (5 sig2 / 3 + 4 sig0 / 3) phi2 - 2 sig0 Phi0 / 3 = -2 Q / 3
+// with D0 = 1 / (3 sig1), D2 = 3 / (7 sig3) and sig_n = sigt - sigs_n the removal of the n-th
+// moment. The higher moments are only applied to the within group scattering.
+pub fn sp3_method(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> SolutionResults {
+    let (energygroups, mattypes) = (variables.energygroups, variables.mattypes);
+    let groups = energygroups as usize;
+    let n = meshid.len();
+
+    let mut operators: (Vec<TriDiagonal>, Vec<TriDiagonal>) = (Vec::new(), Vec::new());
+    // Boundary coupling of the two equations at the left and right edges
+    let mut coupling: Vec<[[[f32; 2]; 2]; 2]> = Vec::new();
+    let mut sig0: Vec<Vec<f32>> = Vec::new();
+    for g in 0..groups {
+        let index = |mesh: &Mesh| (mesh.matid + mattypes * g as u8) as usize;
+        let removal0: Vec<f32> = meshid
+            .iter()
+            .map(|mesh| {
+                xsdata.sigt[index(mesh)]
+                    - xsdata.scat_matrix[groups.pow(2) * mesh.matid as usize + (groups + 1) * g]
+            })
+            .collect();
+        let removal2: Vec<f32> = meshid
+            .iter()
+            .zip(removal0.iter())
+            .map(|(mesh, sig0)| {
+                5.0 / 3.0 * (xsdata.sigt[index(mesh)] - xsdata.sigs2[index(mesh)])
+                    + 4.0 / 3.0 * sig0
+            })
+            .collect();
+        let d0: Vec<f32> = meshid
+            .iter()
+            .map(|mesh| xsdata.inv_sigtr[index(mesh)] / 3.0)
+            .collect();
+        let d2: Vec<f32> = meshid
+            .iter()
+            .map(|mesh| 3.0 / (7.0 * (xsdata.sigt[index(mesh)] - xsdata.sigs3[index(mesh)])))
+            .collect();
+
+        let left = marshak(
+            2.0 * d0[0] / meshid[0].delta_x,
+            2.0 * d2[0] / meshid[0].delta_x,
+            variables.boundl,
+        );
+        let right = marshak(
+            2.0 * d0[n - 1] / meshid[n - 1].delta_x,
+            2.0 * d2[n - 1] / meshid[n - 1].delta_x,
+            variables.boundr,
+        );
+        operators
+            .0
+            .push(sp3_matrix(&d0, &removal0, meshid, left[0][0], right[0][0]));
+        operators
+            .1
+            .push(sp3_matrix(&d2, &removal2, meshid, left[1][1], right[1][1]));
+        coupling.push([left, right]);
+        sig0.push(removal0);
+    }
+    let inner0 = InnerSolver::new(operators.0, settings, true);
+    let inner2 = InnerSolver::new(operators.1, settings, true);
+
+    let mut big_phi: Vec<Vec<f32>> = vec![vec![1.0; n]; groups];
+    let mut phi2: Vec<Vec<f32>> = vec![vec![0.0; n]; groups];
+    let scalar = |big_phi: &[Vec<f32>], phi2: &[Vec<f32>]| -> Vec<Vec<f32>> {
+        big_phi
+            .iter()
+            .zip(phi2)
+            .map(|(x, y)| x.iter().zip(y).map(|(a, b)| a - 2.0 * b).collect())
+            .collect()
+    };
+    let mut flux = scalar(&big_phi, &phi2);
+    let mut q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+    let (mut k, mut delta_flux, mut delta_k): (f32, f32, f32) = (1.0, 1.0, 1.0);
+
+    let mut delta_source: f32 = 1.0;
+    let mut history: Vec<[f32; 4]> = Vec::new();
+
+    let mut outer = 0;
+    while (delta_flux >= settings.flux_tolerance
+        || delta_source >= settings.source_tolerance
+        || delta_k >= settings.k_tolerance)
+        && outer < settings.max_outers
+    {
+        outer += 1;
+        let temp_flux = flux.clone();
+        for g in 0..groups {
+            // Fission and inscattering, integrated over each mesh
+            let source: Vec<f32> = (0..n)
+                .map(|index| {
+                    q[g][index] / k + scat_calc(index, xsdata, meshid, &flux, g, energygroups)
+                })
+                .collect();
+            let [left, right] = coupling[g];
+            for _ in 0..COUPLING_ITERATIONS {
+                let previous: Vec<f32> = big_phi[g].clone();
+                let mut source0: Vec<f32> = (0..n)
+                    .map(|x| source[x] + 2.0 * sig0[g][x] * meshid[x].delta_x * phi2[g][x])
+                    .collect();
+                source0[0] -= left[0][1] * phi2[g][0];
+                source0[n - 1] -= right[0][1] * phi2[g][n - 1];
+                big_phi[g] = inner0.solve(g, &source0, &big_phi[g]).0;
+
+                let mut source2: Vec<f32> = (0..n)
+                    .map(|x| {
+                        2.0 / 3.0 * (sig0[g][x] * meshid[x].delta_x * big_phi[g][x] - source[x])
+                    })
+                    .collect();
+                source2[0] -= left[1][0] * big_phi[g][0];
+                source2[n - 1] -= right[1][0] * big_phi[g][n - 1];
+                phi2[g] = inner2.solve(g, &source2, &phi2[g]).0;
+
+                let change = big_phi[g]
+                    .iter()
+                    .zip(previous.iter())
+                    .map(|(x, y)| ((x - y) / x).abs())
+                    .fold(0.0, f32::max);
+                if change < settings.inner_tolerance {
+                    break;
+                }
+            }
+            flux[g] = big_phi[g]
+                .iter()
+                .zip(phi2[g].iter())
+                .map(|(x, y)| x - 2.0 * y)
+                .collect();
+        }
+
+        let temp_q = q;
+        q = q_gen(xsdata, energygroups, mattypes, &flux, meshid);
+        let temp_k = k;
+        k *= q.iter().flatten().sum::<f32>() / temp_q.iter().flatten().sum::<f32>();
+        delta_k = ((k - temp_k) / temp_k).abs();
+        delta_flux = flux
+            .iter()
+            .flatten()
+            .zip(temp_flux.iter().flatten())
+            .map(|(x, y)| ((x - y) / x).abs())
+            .fold(0.0, f32::max);
+        delta_source = source_residual(&q, &temp_q);
+        history.push([k, delta_k, delta_flux, delta_source]);
+    }
+    match delta_flux < settings.flux_tolerance
+        && delta_source < settings.source_tolerance
+        && delta_k < settings.k_tolerance
+    {
+        true => println!("SP3: converged in {} outers", outer),
+        false => println!(
+            "warning: SP3 not converged after {} outers, flux {:.3e}, fission source {:.3e}, k {:.3e}",
+            outer, delta_flux, delta_source, delta_k
+        ),
+    }
+
+    let flux = power_normalize(flux, xsdata, meshid, variables);
+    let temp_flux = flux.clone();
+
+    println!("{:.10}", k);
+
+    SolutionResults {
+        flux,
+        assembly_average: average_assembly(temp_flux, variables.numass, energygroups),
+        fission_source: Vec::new(),
+        k: vec![k],
+        k_fund: Vec::new(),
+        multiplication: Vec::new(),
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history,
+        generation_source: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::{direct_settings, library, slab_meshes, slab_variables};
+    use crate::sp3::sp3_method;
+    use crate::{Variables, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_critical_slab() {
+        // One group bare Pu-239 slab at its critical thickness of 3.707444 cm (Sood et al.,
+        // PUa-1-0-SL). In slab geometry SP3 is the P3 approximation, whose eigenvalue with
+        // Marshak boundaries is 0.971718 against the transport k of 1.
+        let xsdata = XSData {
+            sigt: vec![0.32640],
+            sigs: vec![0.225216],
+            siga: vec![0.101184],
+            sigf: vec![0.081600],
+            nut: vec![3.24],
+            chit: vec![1.0],
+            scat_matrix: vec![0.225216],
+            inv_sigtr: vec![1.0 / 0.32640],
+            ..library(1)
+        };
+        let (cells, width) = (200, 3.707444_f32);
+        let meshid = slab_meshes(cells, width / cells as f32, |_| 0);
+        let variables = Variables {
+            boundl: 0.0,
+            boundr: 0.0,
+            ..slab_variables(1, 1)
+        };
+
+        let results = sp3_method(&xsdata, &meshid, &variables, &direct_settings());
+        assert!(results.k[0].approx_eq(0.971718, (1e-4, 2)));
+    }
+}
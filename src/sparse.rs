@@ -0,0 +1,428 @@
+use crate::discrete::TriDiagonal;
+use crate::Preconditioner;
+
+// Compressed sparse row storage. Column indices are sorted within each row.
+// The Krylov solvers work in double precision since their recurrences stall at the single
+// precision residual floor of fine mesh operators.
+pub struct CsrMatrix {
+    pub row_ptr: Vec<usize>,
+    pub col_idx: Vec<usize>,
+    pub values: Vec<f64>,
+}
+
+impl CsrMatrix {
+    pub fn len(&self) -> usize {
+        self.row_ptr.len() - 1
+    }
+
+    pub fn mat_vec(&self, x: &[f64]) -> Vec<f64> {
+        (0..self.len())
+            .map(|i| {
+                (self.row_ptr[i]..self.row_ptr[i + 1])
+                    .map(|kk| self.values[kk] * x[self.col_idx[kk]])
+                    .sum()
+            })
+            .collect()
+    }
+
+    // Position of the (i, j) entry in values, if it is part of the sparsity pattern
+    fn position(&self, i: usize, j: usize) -> Option<usize> {
+        let row = &self.col_idx[self.row_ptr[i]..self.row_ptr[i + 1]];
+        row.binary_search(&j).ok().map(|kk| self.row_ptr[i] + kk)
+    }
+
+    fn diagonal(&self) -> Vec<f64> {
+        (0..self.len())
+            .map(|i| self.position(i, i).map_or(0.0, |kk| self.values[kk]))
+            .collect()
+    }
+}
+
+impl From<&TriDiagonal> for CsrMatrix {
+    fn from(a: &TriDiagonal) -> Self {
+        let n = a.len();
+        let mut row_ptr = Vec::with_capacity(n + 1);
+        let mut col_idx = Vec::with_capacity(3 * n);
+        let mut values = Vec::with_capacity(3 * n);
+
+        row_ptr.push(0);
+        for i in 0..n {
+            if i > 0 {
+                col_idx.push(i - 1);
+                values.push(a.lower[i] as f64);
+            }
+            col_idx.push(i);
+            values.push(a.diag[i] as f64);
+            if i + 1 < n {
+                col_idx.push(i + 1);
+                values.push(a.upper[i] as f64);
+            }
+            row_ptr.push(col_idx.len());
+        }
+        CsrMatrix {
+            row_ptr,
+            col_idx,
+            values,
+        }
+    }
+}
+
+// Preconditioner built once from each group operator
+pub enum Preconditioning {
+    Identity,
+    Jacobi(Vec<f64>),
+    Ilu0(CsrMatrix),
+}
+
+impl Preconditioning {
+    pub fn new(kind: &Preconditioner, a: &CsrMatrix) -> Self {
+        match kind {
+            Preconditioner::Identity => Preconditioning::Identity,
+            Preconditioner::Jacobi => {
+                Preconditioning::Jacobi(a.diagonal().iter().map(|x| 1.0 / x).collect())
+            }
+            Preconditioner::Ilu0 => Preconditioning::Ilu0(ilu0(a)),
+        }
+    }
+
+    // Returns M^-1 r
+    fn apply(&self, r: &[f64]) -> Vec<f64> {
+        match self {
+            Preconditioning::Identity => r.to_vec(),
+            Preconditioning::Jacobi(inv_diag) => {
+                r.iter().zip(inv_diag.iter()).map(|(x, y)| x * y).collect()
+            }
+            Preconditioning::Ilu0(lu) => {
+                let n = lu.len();
+                let mut z = r.to_vec();
+                // Forward substitution with the unit lower factor
+                for i in 0..n {
+                    for kk in lu.row_ptr[i]..lu.row_ptr[i + 1] {
+                        if lu.col_idx[kk] < i {
+                            z[i] -= lu.values[kk] * z[lu.col_idx[kk]];
+                        }
+                    }
+                }
+                // Back substitution with the upper factor
+                for i in (0..n).rev() {
+                    let mut diag = 1.0;
+                    for kk in lu.row_ptr[i]..lu.row_ptr[i + 1] {
+                        match lu.col_idx[kk] {
+                            j if j > i => z[i] -= lu.values[kk] * z[j],
+                            j if j == i => diag = lu.values[kk],
+                            _ => {}
+                        }
+                    }
+                    z[i] /= diag;
+                }
+                z
+            }
+        }
+    }
+}
+
+// Incomplete LU factorisation restricted to the sparsity pattern of a. L (unit diagonal) and U
+// are stored together in the returned matrix.
+fn ilu0(a: &CsrMatrix) -> CsrMatrix {
+    let mut lu = CsrMatrix {
+        row_ptr: a.row_ptr.clone(),
+        col_idx: a.col_idx.clone(),
+        values: a.values.clone(),
+    };
+    let diag_ptr: Vec<usize> = (0..a.len())
+        .map(|i| a.position(i, i).expect("ILU(0) needs a full diagonal"))
+        .collect();
+
+    for i in 1..lu.len() {
+        for kk in lu.row_ptr[i]..lu.row_ptr[i + 1] {
+            let k = lu.col_idx[kk];
+            if k >= i {
+                break;
+            }
+            lu.values[kk] /= lu.values[diag_ptr[k]];
+            for jj in kk + 1..lu.row_ptr[i + 1] {
+                if let Some(p) = lu.position(k, lu.col_idx[jj]) {
+                    lu.values[jj] -= lu.values[kk] * lu.values[p];
+                }
+            }
+        }
+    }
+    lu
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
+
+fn norm(a: &[f64]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn residual(a: &CsrMatrix, b: &[f64], x: &[f64]) -> Vec<f64> {
+    b.iter().zip(a.mat_vec(x)).map(|(b, ax)| b - ax).collect()
+}
+
+// Converts the iterate back to single precision along with the mean residual reduction
+fn finish(x: Vec<f64>, iterations: usize, initial: f64, last: f64) -> (Vec<f32>, usize, f32) {
+    let rate = if iterations > 0 && initial > 0.0 {
+        (last / initial).powf(1.0 / iterations as f64) as f32
+    } else {
+        0.0
+    };
+    (x.iter().map(|x| *x as f32).collect(), iterations, rate)
+}
+
+// Preconditioned conjugate gradient, for the symmetric diffusion operator
+pub fn conjugate_gradient(
+    a: &CsrMatrix,
+    m: &Preconditioning,
+    b: &[f32],
+    guess: &[f32],
+    tolerance: f32,
+    max_iterations: usize,
+) -> (Vec<f32>, usize, f32) {
+    let b: Vec<f64> = b.iter().map(|x| *x as f64).collect();
+    let b_norm = norm(&b);
+    let mut x: Vec<f64> = guess.iter().map(|x| *x as f64).collect();
+    let mut r = residual(a, &b, &x);
+    let initial = norm(&r);
+    let tolerance = tolerance as f64 * b_norm;
+    if initial <= tolerance {
+        return finish(x, 0, initial, initial);
+    }
+    let mut z = m.apply(&r);
+    let mut p = z.clone();
+    let mut rz = dot(&r, &z);
+
+    for iteration in 1..=max_iterations {
+        let ap = a.mat_vec(&p);
+        let alpha = rz / dot(&p, &ap);
+        for i in 0..x.len() {
+            x[i] += alpha * p[i];
+            r[i] -= alpha * ap[i];
+        }
+        let r_norm = norm(&r);
+        if r_norm <= tolerance || iteration == max_iterations {
+            return finish(x, iteration, initial, r_norm);
+        }
+        z = m.apply(&r);
+        let rz_new = dot(&r, &z);
+        let beta = rz_new / rz;
+        rz = rz_new;
+        for i in 0..p.len() {
+            p[i] = z[i] + beta * p[i];
+        }
+    }
+    finish(x, max_iterations, initial, initial)
+}
+
+// Right preconditioned BiCGSTAB, for non-symmetric operators
+pub fn bicgstab(
+    a: &CsrMatrix,
+    m: &Preconditioning,
+    b: &[f32],
+    guess: &[f32],
+    tolerance: f32,
+    max_iterations: usize,
+) -> (Vec<f32>, usize, f32) {
+    let n = b.len();
+    let b: Vec<f64> = b.iter().map(|x| *x as f64).collect();
+    let b_norm = norm(&b);
+    let mut x: Vec<f64> = guess.iter().map(|x| *x as f64).collect();
+    let mut r = residual(a, &b, &x);
+    let initial = norm(&r);
+    let tolerance = tolerance as f64 * b_norm;
+    if initial <= tolerance {
+        return finish(x, 0, initial, initial);
+    }
+    let r_hat = r.clone();
+    let (mut rho, mut alpha, mut omega): (f64, f64, f64) = (1.0, 1.0, 1.0);
+    let mut v = vec![0.0; n];
+    let mut p = vec![0.0; n];
+
+    for iteration in 1..=max_iterations {
+        let rho_new = dot(&r_hat, &r);
+        if rho_new == 0.0 || omega == 0.0 {
+            // Breakdown, return the best iterate so far
+            let r_norm = norm(&r);
+            return finish(x, iteration - 1, initial, r_norm);
+        }
+        let beta = (rho_new / rho) * (alpha / omega);
+        rho = rho_new;
+        for i in 0..n {
+            p[i] = r[i] + beta * (p[i] - omega * v[i]);
+        }
+        let p_hat = m.apply(&p);
+        v = a.mat_vec(&p_hat);
+        alpha = rho / dot(&r_hat, &v);
+        let s: Vec<f64> = (0..n).map(|i| r[i] - alpha * v[i]).collect();
+        let s_norm = norm(&s);
+        if s_norm <= tolerance {
+            for i in 0..n {
+                x[i] += alpha * p_hat[i];
+            }
+            return finish(x, iteration, initial, s_norm);
+        }
+        let s_hat = m.apply(&s);
+        let t = a.mat_vec(&s_hat);
+        omega = dot(&t, &s) / dot(&t, &t);
+        for i in 0..n {
+            x[i] += alpha * p_hat[i] + omega * s_hat[i];
+            r[i] = s[i] - omega * t[i];
+        }
+        let r_norm = norm(&r);
+        if r_norm <= tolerance || iteration == max_iterations {
+            return finish(x, iteration, initial, r_norm);
+        }
+    }
+    finish(x, max_iterations, initial, initial)
+}
+
+// Restarted, right preconditioned GMRES(m) using Givens rotations on the Hessenberg matrix
+pub fn gmres(
+    a: &CsrMatrix,
+    m: &Preconditioning,
+    b: &[f32],
+    guess: &[f32],
+    restart: usize,
+    tolerance: f32,
+    max_iterations: usize,
+) -> (Vec<f32>, usize, f32) {
+    // A Krylov space of no vectors never takes a step
+    let restart = restart.max(1);
+    let n = b.len();
+    let b: Vec<f64> = b.iter().map(|x| *x as f64).collect();
+    let b_norm = norm(&b);
+    let mut x: Vec<f64> = guess.iter().map(|x| *x as f64).collect();
+    let mut r = residual(a, &b, &x);
+    let initial = norm(&r);
+    let mut r_norm = initial;
+    let tolerance = tolerance as f64 * b_norm;
+    if initial <= tolerance {
+        return finish(x, 0, initial, initial);
+    }
+    let mut iteration: usize = 0;
+
+    while iteration < max_iterations {
+        let mut basis: Vec<Vec<f64>> = vec![r.iter().map(|x| x / r_norm).collect()];
+        let mut h = vec![vec![0.0; restart]; restart + 1];
+        let (mut cs, mut sn) = (vec![0.0; restart], vec![0.0; restart]);
+        let mut g = vec![0.0; restart + 1];
+        g[0] = r_norm;
+
+        let mut steps = 0;
+        for j in 0..restart {
+            iteration += 1;
+            steps = j + 1;
+            let mut w = a.mat_vec(&m.apply(&basis[j]));
+            for i in 0..=j {
+                h[i][j] = dot(&w, &basis[i]);
+                for l in 0..n {
+                    w[l] -= h[i][j] * basis[i][l];
+                }
+            }
+            h[j + 1][j] = norm(&w);
+
+            for i in 0..j {
+                let temp = cs[i] * h[i][j] + sn[i] * h[i + 1][j];
+                h[i + 1][j] = -sn[i] * h[i][j] + cs[i] * h[i + 1][j];
+                h[i][j] = temp;
+            }
+            let denominator = (h[j][j].powi(2) + h[j + 1][j].powi(2)).sqrt();
+            cs[j] = h[j][j] / denominator;
+            sn[j] = h[j + 1][j] / denominator;
+            h[j][j] = denominator;
+            h[j + 1][j] = 0.0;
+            g[j + 1] = -sn[j] * g[j];
+            g[j] *= cs[j];
+
+            r_norm = g[j + 1].abs();
+            if r_norm <= tolerance || iteration == max_iterations {
+                break;
+            }
+            let scale = norm(&w);
+            basis.push(w.iter().map(|x| x / scale).collect());
+        }
+
+        // Solve the upper triangular system and update x with the preconditioned basis
+        let mut y = vec![0.0; steps];
+        for i in (0..steps).rev() {
+            y[i] = (g[i] - ((i + 1)..steps).map(|l| h[i][l] * y[l]).sum::<f64>()) / h[i][i];
+        }
+        let mut update = vec![0.0; n];
+        for (i, weight) in y.iter().enumerate() {
+            for l in 0..n {
+                update[l] += weight * basis[i][l];
+            }
+        }
+        for (x, dx) in x.iter_mut().zip(m.apply(&update)) {
+            *x += dx;
+        }
+
+        r = residual(a, &b, &x);
+        r_norm = norm(&r);
+        if r_norm <= tolerance {
+            break;
+        }
+    }
+    finish(x, iteration, initial, r_norm)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::TriDiagonal;
+    use crate::sparse::{bicgstab, conjugate_gradient, gmres, CsrMatrix, Preconditioning};
+    use crate::Preconditioner;
+    use float_cmp::ApproxEq;
+
+    fn test_matrix() -> CsrMatrix {
+        CsrMatrix::from(&TriDiagonal {
+            lower: vec![0.0, -1.0, -1.0, -1.0],
+            diag: vec![4.0, 4.0, 4.0, 4.0],
+            upper: vec![-1.0, -1.0, -1.0, 0.0],
+            row_sum: vec![3.0, 2.0, 2.0, 3.0],
+        })
+    }
+
+    #[test]
+    fn test_csr_from_tridiagonal() {
+        let a = test_matrix();
+        assert_eq!(a.row_ptr, vec![0, 2, 5, 8, 10]);
+        assert_eq!(a.col_idx, vec![0, 1, 0, 1, 2, 1, 2, 3, 2, 3]);
+        assert_eq!(a.mat_vec(&[1.0, 1.0, 1.0, 1.0]), vec![3.0, 2.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_krylov() {
+        let a = test_matrix();
+        // Solution is 1, 2, 3, 4
+        let b = vec![2.0, 4.0, 6.0, 13.0];
+        let guess = vec![0.0; 4];
+
+        for kind in [
+            Preconditioner::Identity,
+            Preconditioner::Jacobi,
+            Preconditioner::Ilu0,
+        ] {
+            let m = Preconditioning::new(&kind, &a);
+            let solutions = [
+                conjugate_gradient(&a, &m, &b, &guess, 1e-6, 100),
+                bicgstab(&a, &m, &b, &guess, 1e-6, 100),
+                gmres(&a, &m, &b, &guess, 2, 1e-6, 100),
+                // A zero restart length is taken as 1 instead of looping forever
+                gmres(&a, &m, &b, &guess, 0, 1e-6, 100),
+            ];
+            for (x, iterations, _) in solutions {
+                assert!(iterations <= 100);
+                for (index, value) in x.iter().enumerate() {
+                    assert!(value.approx_eq((index + 1) as f32, (1e-4, 2)));
+                }
+            }
+        }
+
+        // ILU(0) of a tridiagonal matrix is the exact factorisation
+        let m = Preconditioning::new(&Preconditioner::Ilu0, &a);
+        let (_, iterations, _) = conjugate_gradient(&a, &m, &b, &guess, 1e-6, 100);
+        assert_eq!(iterations, 1);
+    }
+}
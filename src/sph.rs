@@ -0,0 +1,388 @@
+use csv::Writer;
+use std::error::Error;
+
+use crate::cmfd::coarse_cells;
+use crate::collapse::write_library;
+use crate::discrete::power_iteration;
+use crate::homogenize::{homogenize, Homogeneous};
+use crate::{CoarseMesh, Mesh, SolutionResults, SolverSettings, Variables, XSData};
+
+// Largest change of an SPH factor of a converged iteration and the iteration limit
+const SPH_TOLERANCE: f64 = 1e-5;
+const SPH_ITERATIONS: usize = 2000;
+
+pub struct SphResults {
+    pub factors: Vec<Vec<f64>>, // [region][group]
+    pub xsdata: XSData,         // SPH corrected library, one material per region
+}
+
+// Library of the homogenized regions with every reaction cross section of group g multiplied
+// by the factor mu_g and the diffusion coefficient divided by it
+fn region_library(constants: &[Homogeneous], factors: &[Vec<f64>], xsdata: &XSData) -> XSData {
+    let (regions, groups) = (constants.len(), constants[0].sigt.len());
+    let field = |value: &dyn Fn(&Homogeneous, usize) -> f64, corrected: bool| -> Vec<f32> {
+        (0..groups)
+            .flat_map(|g| {
+                (0..regions).map(move |i| {
+                    let mu = match corrected {
+                        true => factors[i][g],
+                        false => 1.0,
+                    };
+                    (mu * value(&constants[i], g)) as f32
+                })
+            })
+            .collect()
+    };
+    let sigs = field(&|c, g| c.scatter[g].iter().sum(), true);
+    let sigt = field(&|c, g| c.sigt[g], true);
+    let inv_sigtr: Vec<f32> = (0..groups)
+        .flat_map(|g| {
+            (0..regions).map(move |i| (3.0 * constants[i].diffusion[g] / factors[i][g]) as f32)
+        })
+        .collect();
+    XSData {
+        mu: (0..sigt.len())
+            .map(|x| match sigs[x] > 0.0 {
+                true => (sigt[x] - 1.0 / inv_sigtr[x]) / sigs[x],
+                false => 0.0,
+            })
+            .collect(),
+        sigt,
+        sigs,
+        sigs2: vec![0.0; regions * groups],
+        sigs3: vec![0.0; regions * groups],
+        siga: field(&|c, g| c.siga[g], true),
+        sigf: field(&|c, g| c.sigf[g], true),
+        nut: field(
+            &|c, g| match c.sigf[g] > 0.0 {
+                true => c.nu_sigf[g] / c.sigf[g],
+                false => 0.0,
+            },
+            false,
+        ),
+        chit: field(&|c, g| c.chi[g], false),
+        chid: field(&|c, g| c.chi[g], false),
+        beta: xsdata.beta.clone(),
+        lambda: xsdata.lambda.clone(),
+        velocity: xsdata.velocity.clone(),
+        scat_matrix: (0..regions)
+            .flat_map(|i| {
+                (0..groups).flat_map(move |from| {
+                    (0..groups)
+                        .map(move |to| (factors[i][from] * constants[i].scatter[from][to]) as f32)
+                })
+            })
+            .collect(),
+        inv_sigtr,
+        source: Vec::new(),
+    }
+}
+
+// Superhomogenization of the pin or assembly cells of a heterogeneous solution. Every cell
+// takes its flux volume weighted constants, the homogeneous problem has one mesh per pin cell
+// (split where an assembly edge cuts one), and the factors
+//     mu = reference flux / homogeneous flux
+// of the cell averages are iterated until the homogeneous solution keeps the reference
+// reaction rates of every cell. The homogeneous flux of each group is normalized to the
+// reference flux volume integral. The homogeneous problem goes through the finite difference
+// power iteration with the inner solver and acceleration of the settings.
+pub fn sph_method(
+    results: &SolutionResults,
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+    cells: &CoarseMesh,
+) -> Result<SphResults, Box<dyn Error>> {
+    let groups = variables.energygroups as usize;
+    let regions = coarse_cells(meshid, cells, variables.numass);
+
+    let constants: Vec<Homogeneous> = regions
+        .iter()
+        .map(|(start, end)| homogenize(&results.flux, xsdata, meshid, variables, *start..*end))
+        .collect();
+    let reference: Vec<Vec<f64>> = regions
+        .iter()
+        .map(|(start, end)| {
+            (0..groups)
+                .map(|g| {
+                    (*start..*end)
+                        .map(|x| (results.flux[g][x] * meshid[x].delta_x) as f64)
+                        .sum()
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut edges: Vec<usize> = coarse_cells(meshid, &CoarseMesh::Pin, variables.numass)
+        .iter()
+        .chain(regions.iter())
+        .flat_map(|(start, end)| [*start, *end])
+        .collect();
+    edges.sort_unstable();
+    edges.dedup();
+    // The homogeneous problem takes region i as its material i, its meshes run from the left
+    if regions.len() > u8::MAX as usize {
+        return Err(format!(
+            "{} cells are more than the {} materials of the homogeneous problem",
+            regions.len(),
+            u8::MAX
+        )
+        .into());
+    }
+    let mut homogeneous_meshes: Vec<Mesh> = Vec::new();
+    for edge in edges.windows(2) {
+        let mesh_left = homogeneous_meshes
+            .last()
+            .map_or(0.0, |mesh| mesh.mesh_right);
+        let delta_x: f32 = meshid[edge[0]..edge[1]].iter().map(|x| x.delta_x).sum();
+        homogeneous_meshes.push(Mesh {
+            matid: regions
+                .iter()
+                .position(|(start, end)| *start <= edge[0] && edge[0] < *end)
+                .unwrap() as u8,
+            delta_x,
+            mesh_left,
+            mesh_right: mesh_left + delta_x,
+        });
+    }
+    if homogeneous_meshes.len() < 2 {
+        return Err("the homogeneous problem needs at least two cells".into());
+    }
+    let homogeneous_variables = Variables {
+        mattypes: regions.len() as u8,
+        numass: 1,
+        ..variables.clone()
+    };
+
+    let mut factors = vec![vec![1.0; groups]; regions.len()];
+    // Every homogeneous solve starts from the last one
+    let mut k: f32 = 1.0;
+    let mut flux = vec![vec![1.0; homogeneous_meshes.len()]; groups];
+    for iteration in 1..=SPH_ITERATIONS {
+        let library = region_library(&constants, &factors, xsdata);
+        let solution = power_iteration(
+            &library,
+            &homogeneous_meshes,
+            &homogeneous_variables,
+            settings,
+            k,
+            flux,
+            false,
+        );
+        (k, flux) = (solution.k, solution.flux);
+        if !k.is_finite() {
+            return Err(format!(
+                "homogeneous problem diverged in SPH iteration {}",
+                iteration
+            )
+            .into());
+        }
+
+        let mut homogeneous = vec![vec![0.0; groups]; regions.len()];
+        for (x, mesh) in homogeneous_meshes.iter().enumerate() {
+            for g in 0..groups {
+                homogeneous[mesh.matid as usize][g] += (flux[g][x] * mesh.delta_x) as f64;
+            }
+        }
+        let mut change: f64 = 0.0;
+        for g in 0..groups {
+            let normalization = (0..regions.len()).map(|i| reference[i][g]).sum::<f64>()
+                / (0..regions.len()).map(|i| homogeneous[i][g]).sum::<f64>();
+            for i in 0..regions.len() {
+                let factor = reference[i][g] / (normalization * homogeneous[i][g]);
+                change = change.max((factor - factors[i][g]).abs());
+                factors[i][g] = factor;
+            }
+        }
+        if change < SPH_TOLERANCE {
+            println!(
+                "SPH: {} cells converged in {} iterations, k {:.6} against the reference {:.6}",
+                regions.len(),
+                iteration,
+                k,
+                results.k_eff()
+            );
+            return Ok(SphResults {
+                xsdata: region_library(&constants, &factors, xsdata),
+                factors,
+            });
+        }
+    }
+    Err(format!(
+        "SPH factors not converged in {} iterations, k {:.6}",
+        SPH_ITERATIONS, k
+    )
+    .into())
+}
+
+// Factors of every cell and group to sph.csv, the corrected library in the deck format to
+// sph_xs.txt with the cells numbered from the left as its materials
+pub fn write_sph(sph: &SphResults) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_path("./sph.csv")?;
+    wtr.write_record(["cell", "group", "factor"])?;
+    for (cell, factors) in sph.factors.iter().enumerate() {
+        for (g, factor) in factors.iter().enumerate() {
+            wtr.write_record([cell.to_string(), (g + 1).to_string(), factor.to_string()])?;
+        }
+    }
+    wtr.flush()?;
+    write_library(
+        &sph.xsdata,
+        sph.factors.len(),
+        sph.factors.first().map_or(0, |x| x.len()),
+        "./sph_xs.txt",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::nalgebra_method;
+    use crate::discrete::tests::{direct_settings, library, slab_meshes, slab_variables};
+    use crate::sph::sph_method;
+    use crate::{CoarseMesh, Mesh, Variables, XSData};
+    use float_cmp::ApproxEq;
+
+    #[test]
+    fn test_reaction_rates_preserved() {
+        // One group lattice of alternating fuel and water cells of 10 meshes each, reflected.
+        // Homogenized to one mesh per cell the flux dip in the water is lost, the SPH factors
+        // restore the absorption of every cell and with it the reference k.
+        let xsdata = XSData {
+            sigt: vec![0.5, 1.0],
+            sigs: vec![0.35, 0.98],
+            siga: vec![0.15, 0.02],
+            sigf: vec![0.1, 0.0],
+            nut: vec![2.0, 0.0],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![0.35, 0.98],
+            inv_sigtr: vec![2.0, 1.0],
+            ..library(2)
+        };
+        let meshid = slab_meshes(60, 0.2, |i| ((i / 10) % 2) as u8);
+        let variables = slab_variables(2, 1);
+        let settings = direct_settings();
+        let reference = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+        let sph = sph_method(
+            &reference,
+            &xsdata,
+            &meshid,
+            &variables,
+            &settings,
+            &CoarseMesh::Pin,
+        )
+        .unwrap();
+        assert_eq!(sph.factors.len(), 6);
+        assert!(sph.factors[1][0] != 1.0);
+
+        // The corrected library on one mesh per cell gives back k and the absorption rate of
+        // every cell
+        let cells = slab_meshes(6, 2.0, |i| i as u8);
+        let homogeneous_variables = slab_variables(6, 1);
+        let homogeneous = nalgebra_method(&sph.xsdata, &cells, &homogeneous_variables, &settings);
+        assert!(homogeneous.k[0].approx_eq(reference.k[0], (1e-4, 2)));
+        // Absorption of every cell as a fraction of the total
+        let absorption = |flux: &[f32], siga: &[f32], meshes: &[Mesh]| -> Vec<f32> {
+            let mut rates = [0.0; 6];
+            let per_cell = meshes.len() / 6;
+            for (x, mesh) in meshes.iter().enumerate() {
+                rates[x / per_cell] += siga[mesh.matid as usize] * flux[x] * mesh.delta_x;
+            }
+            let total: f32 = rates.iter().sum();
+            rates.iter().map(|x| x / total).collect()
+        };
+        let reference_rates = absorption(&reference.flux[0], &xsdata.siga, &meshid);
+        let homogeneous_rates = absorption(&homogeneous.flux[0], &sph.xsdata.siga, &cells);
+        for (x, y) in reference_rates.iter().zip(homogeneous_rates.iter()) {
+            assert!(x.approx_eq(*y, (1e-4, 2)));
+        }
+    }
+
+    #[test]
+    fn test_pin_cells_four_groups() {
+        // Four group lattice of 24 alternating fuel and water cells of 4 meshes, with
+        // upscattering out of the last group. The library of 24 cells in 4 groups has 384
+        // scattering entries, past the reach of u8 index arithmetic in the operators.
+        let scatter: [[f32; 4]; 8] = [
+            // fuel, from group 1 to 4
+            [0.2, 0.02, 0.0, 0.0],
+            [0.0, 0.3, 0.02, 0.0],
+            [0.0, 0.0, 0.35, 0.03],
+            [0.0, 0.0, 0.005, 0.4],
+            // water
+            [0.3, 0.08, 0.0, 0.0],
+            [0.0, 0.5, 0.08, 0.0],
+            [0.0, 0.0, 0.8, 0.1],
+            [0.0, 0.0, 0.02, 1.2],
+        ];
+        // Indexed material + 2 * group
+        let siga = vec![0.01, 0.0005, 0.02, 0.001, 0.08, 0.01, 0.15, 0.02];
+        let sigs: Vec<f32> = (0..8)
+            .map(|x| scatter[4 * (x % 2) + x / 2].iter().sum())
+            .collect();
+        let sigt: Vec<f32> = sigs.iter().zip(siga.iter()).map(|(x, y)| x + y).collect();
+        let xsdata = XSData {
+            inv_sigtr: sigt.iter().map(|x| 1.0 / x).collect(),
+            sigt,
+            sigs,
+            siga,
+            sigf: vec![0.005, 0.0, 0.01, 0.0, 0.05, 0.0, 0.1, 0.0],
+            nut: vec![2.5, 0.0, 2.5, 0.0, 2.5, 0.0, 2.5, 0.0],
+            chit: vec![0.7, 0.0, 0.3, 0.0, 0.0, 0.0, 0.0, 0.0],
+            scat_matrix: scatter.concat(),
+            ..library(8)
+        };
+        let meshid = slab_meshes(96, 0.3, |i| ((i / 4) % 2) as u8);
+        let variables = Variables {
+            numrods: 12,
+            mpfr: 4,
+            mpwr: 4,
+            boundr: 0.0,
+            ..slab_variables(2, 4)
+        };
+        let settings = direct_settings();
+        let reference = nalgebra_method(&xsdata, &meshid, &variables, &settings);
+        let sph = sph_method(
+            &reference,
+            &xsdata,
+            &meshid,
+            &variables,
+            &settings,
+            &CoarseMesh::Pin,
+        )
+        .unwrap();
+        assert_eq!(sph.factors.len(), 24);
+
+        // The corrected library keeps k and the thermal absorption of every cell as a fraction
+        // of the total
+        let cells = slab_meshes(24, 1.2, |i| i as u8);
+        let homogeneous_variables = Variables {
+            mattypes: 24,
+            ..variables.clone()
+        };
+        let homogeneous = nalgebra_method(&sph.xsdata, &cells, &homogeneous_variables, &settings);
+        let flux = homogeneous.flux;
+        assert!(homogeneous.k[0].approx_eq(reference.k[0], (1e-4, 2)));
+        let fractions = |rates: Vec<f32>| -> Vec<f32> {
+            let total: f32 = rates.iter().sum();
+            rates.iter().map(|x| x / total).collect()
+        };
+        let reference_rates = fractions(
+            (0..24)
+                .map(|i| {
+                    (4 * i..4 * i + 4)
+                        .map(|x| xsdata.siga[i % 2 + 6] * reference.flux[3][x] * 0.3)
+                        .sum()
+                })
+                .collect(),
+        );
+        let homogeneous_rates = fractions(
+            (0..24)
+                .map(|i| sph.xsdata.siga[i + 72] * flux[3][i] * 1.2)
+                .collect(),
+        );
+        for (x, y) in reference_rates.iter().zip(homogeneous_rates.iter()) {
+            assert!(x.approx_eq(*y, (1e-4, 2)));
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use crate::discrete::{average_assembly, power_normalize};
+use crate::{Mesh, SolutionResults, SolverSettings, Sweep, Variables, XSData};
+
+// Gauss-Legendre directions and weights on [-1, 1] by Newton iteration on P_n, the weights
+// sum to 2 and the directions are sorted from -1 to 1
+pub fn gauss_legendre(order: usize) -> (Vec<f64>, Vec<f64>) {
+    let mut directions = vec![0.0; order];
+    let mut weights = vec![0.0; order];
+    for i in 0..order.div_ceil(2) {
+        let mut x = (std::f64::consts::PI * (i as f64 + 0.75) / (order as f64 + 0.5)).cos();
+        let mut derivative = 1.0;
+        for _ in 0..100 {
+            // P_n(x) and P_n'(x) from the three term recurrence
+            let (mut p0, mut p1) = (1.0, x);
+            for l in 2..=order {
+                let p2 = ((2 * l - 1) as f64 * x * p1 - (l - 1) as f64 * p0) / l as f64;
+                p0 = p1;
+                p1 = p2;
+            }
+            derivative = order as f64 * (x * p1 - p0) / (x * x - 1.0);
+            let step = p1 / derivative;
+            x -= step;
+            if step.abs() < 1e-15 {
+                break;
+            }
+        }
+        let weight = 2.0 / ((1.0 - x * x) * derivative * derivative);
+        directions[i] = -x;
+        directions[order - 1 - i] = x;
+        weights[i] = weight;
+        weights[order - 1 - i] = weight;
+    }
+    (directions, weights)
+}
+
+// Optical thickness below which the step characteristic exponentials are not evaluated
+const THIN_CELL: f64 = 1e-5;
+
+// Cell sweep of one direction with |mu|, returns the outgoing and cell average angular flux
+fn cell_sweep(sweep: &Sweep, psi_in: f64, source: f64, sigt: f64, mu: f64, dx: f64) -> (f64, f64) {
+    match sweep {
+        Sweep::DiamondDifference => {
+            let psi_out = ((mu / dx - 0.5 * sigt) * psi_in + source) / (mu / dx + 0.5 * sigt);
+            match psi_out >= 0.0 {
+                true => (psi_out, 0.5 * (psi_in + psi_out)),
+                // Negative flux fixup, set the outgoing flux to zero and keep the balance
+                false => (0.0, (source + mu * psi_in / dx) / sigt),
+            }
+        }
+        Sweep::StepCharacteristic => {
+            let tau = sigt * dx / mu;
+            if tau < THIN_CELL {
+                // Void or optically thin cell, 1 - exp(-tau) cancels and the sigt -> 0 limit
+                // is taken from the diamond difference relation, exact up to O(tau^2)
+                let psi_out = ((1.0 - 0.5 * tau) * psi_in + source * dx / mu) / (1.0 + 0.5 * tau);
+                return (psi_out, 0.5 * (psi_in + psi_out));
+            }
+            let attenuation = (-tau).exp();
+            let psi_out = psi_in * attenuation + source / sigt * (1.0 - attenuation);
+            (
+                psi_out,
+                source / sigt + mu * (psi_in - psi_out) / (sigt * dx),
+            )
+        }
+    }
+}
+
+// Multigroup S_N solution of the slab. The scattering is isotropic plus linearly anisotropic,
+// with the P1 transfer taken as mu times the P0 transfer of the emitting group. The boundary
+// conditions are albedos, the incoming flux is BoundL or BoundR times the outgoing flux of the
+// mirrored direction.
+pub fn transport_method(
+    xsdata: &XSData,
+    meshid: &[Mesh],
+    variables: &Variables,
+    settings: &SolverSettings,
+) -> SolutionResults {
+    let (energygroups, mattypes) = (variables.energygroups as usize, variables.mattypes as usize);
+    let n = meshid.len();
+    let (directions, weights) = gauss_legendre(settings.sn);
+    let half = settings.sn / 2;
+
+    let field = |values: &[f32]| -> Vec<Vec<f64>> {
+        (0..energygroups)
+            .map(|g| {
+                meshid
+                    .iter()
+                    .map(|mesh| values[mesh.matid as usize + mattypes * g] as f64)
+                    .collect()
+            })
+            .collect()
+    };
+    let sigt = field(&xsdata.sigt);
+    let mu = field(&xsdata.mu);
+    let chi = field(&xsdata.chit);
+    let nu_sigf: Vec<Vec<f64>> = field(&xsdata.nut)
+        .iter()
+        .zip(field(&xsdata.sigf))
+        .map(|(nu, sigf)| nu.iter().zip(sigf).map(|(x, y)| x * y).collect())
+        .collect();
+    let transfer = |index: usize, from: usize, to: usize| -> f64 {
+        xsdata.scat_matrix
+            [energygroups.pow(2) * meshid[index].matid as usize + energygroups * from + to]
+            as f64
+    };
+
+    let mut phi: Vec<Vec<f64>> = vec![vec![1.0; n]; energygroups];
+    let mut current: Vec<Vec<f64>> = vec![vec![0.0; n]; energygroups];
+    // Outgoing angular flux at the right edge of every direction with mu > 0, the incoming flux
+    // of the right boundary is lagged by one sweep
+    let mut right_edge: Vec<Vec<f64>> = vec![vec![0.0; half]; energygroups];
+    let production = |phi: &[Vec<f64>]| -> Vec<f64> {
+        (0..n)
+            .map(|i| (0..energygroups).map(|g| nu_sigf[g][i] * phi[g][i]).sum())
+            .collect()
+    };
+    let mut fission = production(&phi);
+    let mut k: f64 = 1.0;
+
+    let mut history: Vec<[f32; 4]> = Vec::new();
+
+    let mut outer = 0;
+    let mut total_inners = 0;
+    let converged = loop {
+        outer += 1;
+        let temp_phi = phi.clone();
+        for g in 0..energygroups {
+            // Fission and group to group scattering moments, fixed during the inner iteration
+            let (q0, q1): (Vec<f64>, Vec<f64>) = (0..n)
+                .map(|i| {
+                    let mut q0 = chi[g][i] * fission[i] / k;
+                    let mut q1 = 0.0;
+                    for from in (0..energygroups).filter(|from| *from != g) {
+                        q0 += transfer(i, from, g) * phi[from][i];
+                        q1 += mu[from][i] * transfer(i, from, g) * current[from][i];
+                    }
+                    (q0, q1)
+                })
+                .unzip();
+
+            for _ in 0..settings.max_inner {
+                total_inners += 1;
+                let source: Vec<(f64, f64)> = (0..n)
+                    .map(|i| {
+                        let within = transfer(i, g, g);
+                        (
+                            q0[i] + within * phi[g][i],
+                            q1[i] + mu[g][i] * within * current[g][i],
+                        )
+                    })
+                    .collect();
+                let mut new_phi = vec![0.0; n];
+                let mut new_current = vec![0.0; n];
+
+                // Right to left for mu < 0, then left to right for the mirrored directions
+                let mut left_edge = vec![0.0; half];
+                for m in 0..half {
+                    let direction = directions[m];
+                    let mut psi = variables.boundr as f64 * right_edge[g][half - 1 - m];
+                    for i in (0..n).rev() {
+                        let s = 0.5 * (source[i].0 + 3.0 * direction * source[i].1);
+                        let (psi_out, psi_average) = cell_sweep(
+                            &settings.sweep,
+                            psi,
+                            s,
+                            sigt[g][i],
+                            -direction,
+                            meshid[i].delta_x as f64,
+                        );
+                        new_phi[i] += weights[m] * psi_average;
+                        new_current[i] += weights[m] * direction * psi_average;
+                        psi = psi_out;
+                    }
+                    left_edge[m] = psi;
+                }
+                for m in half..settings.sn {
+                    let direction = directions[m];
+                    let mut psi = variables.boundl as f64 * left_edge[settings.sn - 1 - m];
+                    for i in 0..n {
+                        let s = 0.5 * (source[i].0 + 3.0 * direction * source[i].1);
+                        let (psi_out, psi_average) = cell_sweep(
+                            &settings.sweep,
+                            psi,
+                            s,
+                            sigt[g][i],
+                            direction,
+                            meshid[i].delta_x as f64,
+                        );
+                        new_phi[i] += weights[m] * psi_average;
+                        new_current[i] += weights[m] * direction * psi_average;
+                        psi = psi_out;
+                    }
+                    right_edge[g][m - half] = psi;
+                }
+
+                let change = new_phi
+                    .iter()
+                    .zip(phi[g].iter())
+                    .map(|(x, y)| ((x - y) / x).abs())
+                    .fold(0.0, f64::max);
+                phi[g] = new_phi;
+                current[g] = new_current;
+                if change < settings.inner_tolerance as f64 {
+                    break;
+                }
+            }
+        }
+
+        let new_fission = production(&phi);
+        let volume = |f: &[f64]| -> f64 {
+            f.iter()
+                .zip(meshid)
+                .map(|(x, mesh)| x * mesh.delta_x as f64)
+                .sum()
+        };
+        let new_k = k * volume(&new_fission) / volume(&fission);
+        let delta_source = new_fission
+            .iter()
+            .zip(fission.iter())
+            .filter(|(x, _)| **x > 0.0)
+            .map(|(x, y)| ((x / new_k - y / k) / (x / new_k)).abs())
+            .fold(0.0, f64::max);
+        let delta_k = ((new_k - k) / new_k).abs();
+        let delta_flux = phi
+            .iter()
+            .flatten()
+            .zip(temp_phi.iter().flatten())
+            .map(|(x, y)| ((y - x) / y).abs())
+            .fold(0.0, f64::max);
+        fission = new_fission;
+        k = new_k;
+        history.push([
+            k as f32,
+            delta_k as f32,
+            delta_flux as f32,
+            delta_source as f32,
+        ]);
+        let converged = delta_flux < settings.flux_tolerance as f64
+            && delta_source < settings.source_tolerance as f64
+            && delta_k < settings.k_tolerance as f64;
+        if converged || outer == settings.max_outers {
+            break converged;
+        }
+    };
+    match converged {
+        true => println!(
+            "S{}: converged in {} outers ({} source iterations)",
+            settings.sn, outer, total_inners
+        ),
+        false => println!(
+            "warning: S{} not converged in {} outers ({} source iterations)",
+            settings.sn, outer, total_inners
+        ),
+    }
+
+    let flux = phi
+        .iter()
+        .map(|group| group.iter().map(|x| *x as f32).collect())
+        .collect();
+    let flux = power_normalize(flux, xsdata, meshid, variables);
+    let temp_flux = flux.clone();
+
+    println!("{:.10}", k);
+
+    SolutionResults {
+        flux,
+        assembly_average: average_assembly(temp_flux, variables.numass, variables.energygroups),
+        fission_source: Vec::new(),
+        k: vec![k as f32],
+        k_fund: Vec::new(),
+        multiplication: Vec::new(),
+        modes: Vec::new(),
+        eigenvalues: Vec::new(),
+        history,
+        generation_source: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discrete::tests::{
+        direct_settings, infinite_medium, library, slab_meshes, slab_variables,
+    };
+    use crate::transport::{gauss_legendre, transport_method};
+    use crate::{Mesh, Sweep, Variables, XSData};
+    use float_cmp::ApproxEq;
+
+    fn slab(xsdata: &XSData, cells: usize, width: f32, boundary: f32) -> (Vec<Mesh>, Variables) {
+        let variables = Variables {
+            boundl: boundary,
+            boundr: boundary,
+            ..slab_variables(1, xsdata.sigt.len() as u8)
+        };
+        (slab_meshes(cells, width / cells as f32, |_| 0), variables)
+    }
+
+    #[test]
+    fn test_gauss_legendre() {
+        let (directions, weights) = gauss_legendre(8);
+        assert!(weights.iter().sum::<f64>().approx_eq(2.0, (1e-12, 2)));
+        assert!(directions[7].approx_eq(0.9602898564975363, (1e-12, 2)));
+        // Exact for the polynomials up to order 15
+        let moment: f64 = directions
+            .iter()
+            .zip(weights.iter())
+            .map(|(x, w)| w * x.powi(6))
+            .sum();
+        assert!(moment.approx_eq(2.0 / 7.0, (1e-12, 2)));
+    }
+
+    #[test]
+    fn test_critical_slab() {
+        // One group bare slab of Pu-239 with a critical thickness of 3.707444 cm (Sood et al.,
+        // Analytical Benchmark Test Set for Criticality Code Verification, PUa-1-0-SL)
+        let xsdata = XSData {
+            sigt: vec![0.32640],
+            sigs: vec![0.225216],
+            siga: vec![0.101184],
+            sigf: vec![0.081600],
+            nut: vec![3.24],
+            chit: vec![1.0],
+            scat_matrix: vec![0.225216],
+            inv_sigtr: vec![1.0 / 0.32640],
+            ..library(1)
+        };
+        let (meshid, variables) = slab(&xsdata, 200, 3.707444, 0.0);
+        let mut settings = direct_settings();
+        settings.inner_tolerance = 1e-8;
+        // The S_N error of such a thin slab is still 0.2 % with S16
+        settings.sn = 32;
+        for sweep in [Sweep::DiamondDifference, Sweep::StepCharacteristic] {
+            settings.sweep = sweep;
+            let results = transport_method(&xsdata, &meshid, &variables, &settings);
+            assert!(results.k[0].approx_eq(1.0, (1e-3, 2)));
+        }
+
+        // Reflected, the slab is an infinite medium with k = nu sigf / siga
+        let (meshid, variables) = slab(&xsdata, 20, 3.707444, 1.0);
+        let results = transport_method(&xsdata, &meshid, &variables, &settings);
+        assert!(results.k[0].approx_eq(3.24 * 0.0816 / 0.101184, (1e-4, 2)));
+    }
+
+    #[test]
+    fn test_void_cells() {
+        // Reflected lattice of fuel and void, only the fuel absorbs so k = nu sigf / siga
+        let (fuel, _, _) = infinite_medium(0.2);
+        let xsdata = XSData {
+            sigt: vec![fuel.sigt[0], 0.0],
+            sigs: vec![fuel.sigs[0], 0.0],
+            siga: vec![fuel.siga[0], 0.0],
+            sigf: vec![fuel.sigf[0], 0.0],
+            nut: vec![1.0, 0.0],
+            chit: vec![1.0, 0.0],
+            scat_matrix: vec![fuel.scat_matrix[0], 0.0],
+            inv_sigtr: vec![1.0, 0.0],
+            ..library(2)
+        };
+        let meshid = slab_meshes(20, 0.25, |i| (i >= 10) as u8);
+        let variables = slab_variables(2, 1);
+        let mut settings = direct_settings();
+        settings.sn = 8;
+        for sweep in [Sweep::DiamondDifference, Sweep::StepCharacteristic] {
+            settings.sweep = sweep;
+            let results = transport_method(&xsdata, &meshid, &variables, &settings);
+            assert!(results.k[0].approx_eq(2.0, (1e-4, 2)));
+            assert!(results.flux.iter().flatten().all(|phi| phi.is_finite()));
+        }
+    }
+}